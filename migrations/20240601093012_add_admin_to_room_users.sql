@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE room_users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- rooms created before this migration get their earliest member as admin
+UPDATE room_users ru
+SET is_admin = TRUE
+WHERE ru.joined_at = (SELECT MIN(joined_at) FROM room_users WHERE room_id = ru.room_id)
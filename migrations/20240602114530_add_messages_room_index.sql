@@ -0,0 +1,2 @@
+-- Add migration script here
+CREATE INDEX messages_room_created_at_id_idx ON messages (room, created_at DESC, id DESC)
@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE refresh_tokens (
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    family uuid NOT NULL,
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    expires_at timestamptz NOT NULL,
+    revoked_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX refresh_tokens_family_idx ON refresh_tokens (family)
@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE user_tokens (
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    purpose VARCHAR(32) NOT NULL,
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz,
+    created_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX user_tokens_user_id_purpose_idx ON user_tokens (user_id, purpose)
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE user_identities (
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    issuer VARCHAR(255) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    email VARCHAR(255),
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    UNIQUE (issuer, subject)
+)
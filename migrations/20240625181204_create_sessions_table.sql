@@ -0,0 +1,15 @@
+-- Add migration script here
+-- a session is the login a refresh token family belongs to, `id` is the family
+CREATE TABLE sessions (
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_label VARCHAR(255),
+    ip VARCHAR(45) NOT NULL,
+    user_agent TEXT,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    last_seen_at timestamptz NOT NULL DEFAULT NOW(),
+    revoked_at timestamptz
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id)
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE message_reactions (
+    message_id uuid NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    emoji VARCHAR(64) NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (message_id, user_id, emoji)
+)
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- replies are never parents, a thread is one root message and its replies
+ALTER TABLE messages
+    ADD COLUMN parent_id uuid REFERENCES messages(id) ON DELETE CASCADE,
+    ADD COLUMN reply_count INT NOT NULL DEFAULT 0,
+    ADD COLUMN last_reply_at timestamptz;
+
+CREATE INDEX messages_parent_id_created_at_id_idx ON messages (parent_id, created_at DESC, id DESC)
+    WHERE parent_id IS NOT NULL
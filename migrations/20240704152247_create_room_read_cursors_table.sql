@@ -0,0 +1,12 @@
+-- Add migration script here
+-- the newest message a user has read in a room, by (created_at, id) like the message pages
+CREATE TABLE room_read_cursors (
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    room_id uuid NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    PRIMARY KEY (user_id, room_id),
+    last_read_message_id uuid NOT NULL,
+    last_read_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX room_read_cursors_room_id_idx ON room_read_cursors (room_id)
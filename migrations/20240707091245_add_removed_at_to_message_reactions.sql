@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE message_reactions ADD COLUMN removed_at timestamptz
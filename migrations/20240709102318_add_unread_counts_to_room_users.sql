@@ -0,0 +1,53 @@
+-- Add migration script here
+-- unread and mention counts are kept on the membership as messages are stored, deleted and read
+ALTER TABLE room_users
+    ADD COLUMN unread_count INT NOT NULL DEFAULT 0,
+    ADD COLUMN mention_count INT NOT NULL DEFAULT 0;
+
+-- members mentioned with `@name` in a message as it was sent
+CREATE TABLE message_mentions (
+    message_id uuid NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id uuid NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    PRIMARY KEY (message_id, user_id)
+);
+
+CREATE INDEX message_mentions_user_id_idx ON message_mentions (user_id);
+
+-- mentions of messages which are still unread, read ones are never counted again;
+-- every character of the name other than letters, digits and spaces is escaped
+INSERT INTO message_mentions (message_id, user_id)
+SELECT m.id, ru.user_id
+FROM room_users ru
+INNER JOIN users u ON u.id = ru.user_id
+LEFT JOIN room_read_cursors c ON c.room_id = ru.room_id AND c.user_id = ru.user_id
+INNER JOIN messages m ON m.room = ru.room_id AND m.author <> ru.user_id
+WHERE (m.created_at, m.id) > (
+        COALESCE(c.last_read_at, ru.joined_at),
+        COALESCE(c.last_read_message_id, '00000000-0000-0000-0000-000000000000'::uuid)
+    )
+    AND m.created_at > ru.joined_at
+    AND m.content ~* (
+        '(^|[^[:alnum:]_])@'
+        || regexp_replace(u.name, '([^[:alnum:][:space:]])', '\\\1', 'g')
+        || '($|[^[:alnum:]_])'
+    );
+
+UPDATE room_users ru
+SET unread_count = counts.unread_count,
+    mention_count = counts.mention_count
+FROM (
+    SELECT ru.room_id, ru.user_id,
+        COUNT(m.id)::int AS unread_count,
+        COUNT(mm.message_id)::int AS mention_count
+    FROM room_users ru
+    LEFT JOIN room_read_cursors c ON c.room_id = ru.room_id AND c.user_id = ru.user_id
+    INNER JOIN messages m ON m.room = ru.room_id AND m.author <> ru.user_id
+    LEFT JOIN message_mentions mm ON mm.message_id = m.id AND mm.user_id = ru.user_id
+    WHERE (m.created_at, m.id) > (
+            COALESCE(c.last_read_at, ru.joined_at),
+            COALESCE(c.last_read_message_id, '00000000-0000-0000-0000-000000000000'::uuid)
+        )
+        AND m.created_at > ru.joined_at
+    GROUP BY ru.room_id, ru.user_id
+) counts
+WHERE ru.room_id = counts.room_id AND ru.user_id = counts.user_id
@@ -1,13 +1,137 @@
+use std::net::IpAddr;
+
 use secrecy::{ExposeSecret, Secret};
 
 use crate::errors::{AppError, AppErrorType};
 
+/// `token_max_age` - access token lifetime in minutes \
+/// `refresh_token_max_age` - refresh token lifetime in minutes \
+/// `trusted_proxies` - addresses of load balancers whose `Forwarded` / `X-Forwarded-For`
+/// headers name the client, requests from anywhere else are taken by their peer address
 #[derive(serde::Deserialize)]
 pub struct Settings {
     pub redis: RedisSettings,
     pub database: DatabaseSettings,
     pub token_max_age: i64,
+    pub refresh_token_max_age: i64,
     pub application_port: u16,
+    pub jwt: JwtSettings,
+    pub argon2: Argon2Settings,
+    pub password_policy: PasswordPolicySettings,
+    pub mail: MailSettings,
+    pub two_factor: TwoFactorSettings,
+    pub login_throttle: LoginThrottleSettings,
+    pub oidc: Option<OidcSettings>,
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+/// External OpenID Connect provider, sign-in with it is disabled when not configured. \
+/// `issuer` - discovery is read from `{issuer}/.well-known/openid-configuration` \
+/// `redirect_url` - url of the `/oidc/callback` route as registered with the provider
+#[derive(serde::Deserialize)]
+pub struct OidcSettings {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Secret<String>,
+    pub scopes: Vec<String>,
+    pub redirect_url: String,
+}
+
+/// `window` - seconds failed attempts are counted for \
+/// `max_attempts_per_email`, `max_attempts_per_ip` - failures in a window before a lockout \
+/// `lockout` - seconds an email or ip stays locked \
+/// `delay_after` - failures before attempts get delayed \
+/// `base_delay`, `max_delay` - milliseconds of the first delay, doubled on every next failure
+#[derive(serde::Deserialize)]
+pub struct LoginThrottleSettings {
+    pub window: u64,
+    pub max_attempts_per_email: u64,
+    pub max_attempts_per_ip: u64,
+    pub lockout: u64,
+    pub delay_after: u64,
+    pub base_delay: u64,
+    pub max_delay: u64,
+}
+
+/// `issuer` - name shown in authenticator apps \
+/// `challenge_max_age` - minutes between the password and the code step of a login
+#[derive(serde::Deserialize)]
+pub struct TwoFactorSettings {
+    pub issuer: String,
+    pub challenge_max_age: i64,
+}
+
+/// `sender` - from address of outgoing emails \
+/// `base_url` - frontend url links in emails point to \
+/// `verification_token_max_age`, `password_reset_token_max_age` - token lifetimes in minutes \
+/// `require_verified_email` - refuse logins until the email is verified
+#[derive(serde::Deserialize)]
+pub struct MailSettings {
+    pub sender: String,
+    pub base_url: String,
+    pub verification_token_max_age: i64,
+    pub password_reset_token_max_age: i64,
+    pub require_verified_email: bool,
+    pub transport: MailTransportSettings,
+}
+
+/// `file` writes every email to a file in `path`, `memory` keeps them in memory.
+#[derive(serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum MailTransportSettings {
+    Smtp {
+        host: String,
+        port: u16,
+        username: String,
+        password: Secret<String>,
+    },
+    File {
+        path: String,
+    },
+    Memory,
+}
+
+/// Rules passwords are checked against at registration, lengths are in characters.
+#[derive(serde::Deserialize)]
+pub struct PasswordPolicySettings {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+}
+
+/// Argon2id parameters for new password hashes, stored hashes with other
+/// parameters are rehashed on the next successful login. \
+/// `memory_cost` - memory in KiB \
+/// `time_cost` - number of passes \
+/// `parallelism` - degree of parallelism
+#[derive(serde::Deserialize)]
+pub struct Argon2Settings {
+    pub memory_cost: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+}
+
+/// `signing_kid` - key new tokens are signed with \
+/// `keys` - every key tokens are verified with, published in the JWKS;
+/// keys being rotated out stay here without their private key until their tokens expire
+#[derive(serde::Deserialize)]
+pub struct JwtSettings {
+    pub signing_kid: String,
+    pub keys: Vec<JwtKeySettings>,
+}
+
+/// PEM keys are given inline or as file paths, inline ones take precedence.
+#[derive(serde::Deserialize)]
+pub struct JwtKeySettings {
+    pub kid: String,
+    pub public_key: Option<Secret<String>>,
+    pub public_key_path: Option<String>,
+    pub private_key: Option<Secret<String>>,
+    pub private_key_path: Option<String>,
 }
 
 #[derive(serde::Deserialize)]
@@ -26,17 +150,82 @@ pub struct RedisSettings {
     pub redis_worker_config: RedisWorkerConfig,
 }
 
+/// `key` - stream read by the task \
+/// `interval` - seconds between stream reads \
+/// `batch_size` - max entries read per interval \
+/// `max_attempts` - attempts per event before it is dead-lettered \
+/// `backoff` - milliseconds before the first retry, doubled on every next one
 #[derive(serde::Deserialize)]
 pub struct RedisEventConfig {
     pub key: String,
     pub interval: u64,
-} 
+    pub batch_size: usize,
+    pub max_attempts: u32,
+    pub backoff: u64,
+}
 
+/// `group` - consumer group shared by all instances \
+/// `consumer` - name of this instance in the group, must be stable across restarts \
+/// `claim_idle_time` - seconds after which pending entries of other consumers are claimed,
+/// longer than the longest backoff so entries waiting for a retry are not taken over \
+/// `task_config` - per stream settings, streams without an entry use `RedisEventConfig::default_for`
 #[derive(serde::Deserialize)]
 pub struct RedisWorkerConfig {
+    pub group: String,
+    pub consumer: String,
+    pub claim_idle_time: u64,
+    #[serde(default)]
     pub task_config: Vec<RedisEventConfig>,
 }
 
+impl RedisEventConfig {
+    pub fn default_for(key: &str) -> Self {
+        RedisEventConfig {
+            key: key.to_string(),
+            interval: 1,
+            batch_size: 100,
+            max_attempts: 5,
+            backoff: 1000,
+        }
+    }
+
+    // milliseconds an event which failed `attempts` times waits before the next attempt
+    pub fn retry_delay(&self, attempts: u32) -> u64 {
+        self.backoff.saturating_mul(1 << attempts.saturating_sub(1).min(16))
+    }
+}
+
+impl RedisWorkerConfig {
+    // every stream in `keys` gets a task, configured ones keep their settings
+    pub fn with_default_tasks(mut self, keys: &[&str]) -> Self {
+        for key in keys {
+            if !self.task_config.iter().any(|task| task.key == *key) {
+                self.task_config.push(RedisEventConfig::default_for(key));
+            }
+        }
+
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), AppError> {
+        for task in &self.task_config {
+            let max_backoff = task.retry_delay(task.max_attempts.saturating_sub(1));
+            if self.claim_idle_time.saturating_mul(1000) <= max_backoff {
+                let message = format!(
+                    "claim_idle_time of {}s must be longer than the {}ms backoff of stream {}.",
+                    self.claim_idle_time, max_backoff, task.key
+                );
+                return Err(AppError::new(
+                    message.clone(),
+                    AppErrorType::ConfigurationError(config::ConfigError::Message(message)),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl RedisSettings {
     pub fn redis_connection_string(&self) -> Secret<String> {
         Secret::new(format!("redis://{}:{}", self.host, self.port))
@@ -0,0 +1,67 @@
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::errors::{AppError, AppErrorType};
+
+// tells api tokens apart from jwts in the authorization header
+pub static API_TOKEN_PREFIX: &str = "fc_";
+
+/// What an api token may be used for, a jwt of a login may do everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiTokenScope {
+    MessagesRead,
+    MessagesWrite,
+    RoomsRead,
+    RoomsWrite,
+    // only honored for tokens of admins
+    MetricsRead,
+}
+
+impl ApiTokenScope {
+    pub const ALL: [ApiTokenScope; 5] = [
+        ApiTokenScope::MessagesRead,
+        ApiTokenScope::MessagesWrite,
+        ApiTokenScope::RoomsRead,
+        ApiTokenScope::RoomsWrite,
+        ApiTokenScope::MetricsRead,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiTokenScope::MessagesRead => "messages:read",
+            ApiTokenScope::MessagesWrite => "messages:write",
+            ApiTokenScope::RoomsRead => "rooms:read",
+            ApiTokenScope::RoomsWrite => "rooms:write",
+            ApiTokenScope::MetricsRead => "metrics:read",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Result<ApiTokenScope, AppError> {
+        Self::ALL
+            .into_iter()
+            .find(|known| known.as_str() == scope)
+            .ok_or_else(|| {
+                AppError::new(
+                    format!("Unknown api token scope {}.", scope),
+                    AppErrorType::ValidationError(format!("Unknown scope {}", scope)),
+                )
+            })
+    }
+}
+
+// returns the token to hand out once and the hash to store
+pub fn generate_api_token() -> (String, String) {
+    let token = format!(
+        "{}{}{}",
+        API_TOKEN_PREFIX,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    );
+    let hash = hash_api_token(&token);
+
+    (token, hash)
+}
+
+pub fn hash_api_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
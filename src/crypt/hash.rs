@@ -1,19 +1,91 @@
-use bcrypt::{hash, verify};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
 use secrecy::{ExposeSecret, Secret};
 
-use crate::errors::{AppError, AppErrorType};
+use crate::{
+    configuration::Argon2Settings,
+    errors::{AppError, AppErrorType},
+};
 
-pub fn hash_password(password: String) -> Result<String, AppError> {
-    hash(password, 10).map_err(|e| {
+fn argon2(settings: &Argon2Settings) -> Result<Argon2<'static>, AppError> {
+    let params = Params::new(
+        settings.memory_cost,
+        settings.time_cost,
+        settings.parallelism,
+        None,
+    )
+    .map_err(|e| {
         AppError::new(
-            "Hash password error".to_string(),
-            AppErrorType::EncodingError(e),
+            "Argon2 parameters error.".to_string(),
+            AppErrorType::HashingError(e.into()),
         )
-    })
+    })?;
+
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+pub fn hash_password(password: String, settings: &Argon2Settings) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    argon2(settings)?
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| {
+            AppError::new(
+                "Hash password error".to_string(),
+                AppErrorType::HashingError(e),
+            )
+        })
+}
+
+// accepts argon2 PHC strings and legacy bcrypt hashes,
+// returns whether the stored hash should be replaced by one with the current parameters
+pub fn verify_password(
+    password: Secret<String>,
+    hash: &str,
+    settings: &Argon2Settings,
+) -> Result<bool, AppError> {
+    if !hash.starts_with("$argon2") {
+        return verify_bcrypt_password(password, hash).map(|_| true);
+    }
+
+    let parsed = PasswordHash::new(hash).map_err(|e| {
+        AppError::new(
+            "Stored password hash is malformed.".to_string(),
+            AppErrorType::HashingError(e),
+        )
+    })?;
+
+    match Argon2::default().verify_password(password.expose_secret().as_bytes(), &parsed) {
+        Ok(()) => {}
+        Err(argon2::password_hash::Error::Password) => {
+            return Err(AppError::new(
+                "Password does not match.".to_string(),
+                AppErrorType::PasswordWrongError,
+            ))
+        }
+        Err(e) => {
+            return Err(AppError::new(
+                "Encryption error.".to_string(),
+                AppErrorType::HashingError(e),
+            ))
+        }
+    }
+
+    let outdated = parsed.algorithm != Algorithm::Argon2id.ident()
+        || Params::try_from(&parsed).map_or(true, |params| {
+            params.m_cost() != settings.memory_cost
+                || params.t_cost() != settings.time_cost
+                || params.p_cost() != settings.parallelism
+        });
+
+    Ok(outdated)
 }
 
-pub fn verify_password(password: Secret<String>, hash: &str) -> Result<(), AppError> {
-    match verify(password.expose_secret().as_bytes(), hash) {
+fn verify_bcrypt_password(password: Secret<String>, hash: &str) -> Result<(), AppError> {
+    match bcrypt::verify(password.expose_secret().as_bytes(), hash) {
         Err(e) => Err(AppError::new(
             "Encryption error.".to_string(),
             AppErrorType::EncodingError(e),
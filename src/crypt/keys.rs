@@ -0,0 +1,171 @@
+use std::{collections::HashMap, fs, sync::Arc};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use config::ConfigError;
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, Jwk, JwkSet, KeyAlgorithm, PublicKeyUse,
+        RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use rsa::{pkcs1::DecodeRsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts, RsaPublicKey};
+use secrecy::{ExposeSecret, Secret};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    configuration::{JwtKeySettings, JwtSettings},
+    errors::{AppError, AppErrorType},
+};
+
+/// RS256 keys parsed once at startup. \
+/// Tokens are signed with the `signing_kid` key and carry its `kid`,
+/// every configured key verifies tokens carrying its `kid` and is published in the JWKS.
+#[derive(Clone)]
+pub struct JwtKeys {
+    signing_kid: String,
+    encoding: Arc<EncodingKey>,
+    decoding: Arc<HashMap<String, DecodingKey>>,
+    jwks: Arc<JwkSet>,
+}
+
+impl JwtKeys {
+    pub fn from_settings(settings: &JwtSettings) -> Result<Self, AppError> {
+        let mut encoding = None;
+        let mut decoding = HashMap::new();
+        let mut jwks = Vec::new();
+
+        for key in &settings.keys {
+            let public_pem = read_pem(&key.kid, key.public_key.as_ref(), key.public_key_path.as_ref())?
+                .ok_or_else(|| configuration_error(format!("JWT key {} has no public key.", key.kid)))?;
+
+            decoding.insert(
+                key.kid.clone(),
+                DecodingKey::from_rsa_pem(public_pem.expose_secret().as_bytes())
+                    .map_err(token_error)?,
+            );
+            jwks.push(jwk(key, public_pem.expose_secret())?);
+
+            if key.kid == settings.signing_kid {
+                let private_pem =
+                    read_pem(&key.kid, key.private_key.as_ref(), key.private_key_path.as_ref())?
+                        .ok_or_else(|| {
+                            configuration_error(format!("JWT key {} has no private key.", key.kid))
+                        })?;
+                encoding = Some(
+                    EncodingKey::from_rsa_pem(private_pem.expose_secret().as_bytes())
+                        .map_err(token_error)?,
+                );
+            }
+        }
+
+        let encoding = encoding.ok_or_else(|| {
+            configuration_error(format!("JWT signing key {} is not configured.", settings.signing_kid))
+        })?;
+
+        Ok(Self {
+            signing_kid: settings.signing_kid.clone(),
+            encoding: Arc::new(encoding),
+            decoding: Arc::new(decoding),
+            jwks: Arc::new(JwkSet { keys: jwks }),
+        })
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, AppError> {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(self.signing_kid.clone());
+
+        jsonwebtoken::encode(&header, claims, &self.encoding).map_err(|e| {
+            AppError::new(
+                "Token encoding error.".to_string(),
+                AppErrorType::TokenEncodingError(e),
+            )
+        })
+    }
+
+    // picks the verification key by the `kid` of the token, tokens of any other `audience`
+    // or without one are refused so one kind of token can not stand in for another
+    pub fn decode<T: DeserializeOwned>(&self, token: &str, audience: &str) -> Result<T, AppError> {
+        let kid = jsonwebtoken::decode_header(token)
+            .map_err(|e| {
+                AppError::new(
+                    "Token header decoding error.".to_string(),
+                    AppErrorType::TokenEncodingError(e),
+                )
+            })?
+            .kid
+            .unwrap_or_default();
+        let key = self.decoding.get(&kid).ok_or_else(|| {
+            AppError::new(
+                format!("Token key {} is unknown.", kid),
+                AppErrorType::AuthorizationError("Unknown token key".to_string()),
+            )
+        })?;
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "aud"]);
+
+        jsonwebtoken::decode::<T>(token, key, &validation)
+            .map(|v| v.claims)
+            .map_err(|e| {
+                AppError::new(
+                    "Token decoding error.".to_string(),
+                    AppErrorType::TokenEncodingError(e),
+                )
+            })
+    }
+
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
+
+// inline PEMs take precedence over paths
+fn read_pem(
+    kid: &str,
+    inline: Option<&Secret<String>>,
+    path: Option<&String>,
+) -> Result<Option<Secret<String>>, AppError> {
+    match (inline, path) {
+        (Some(pem), _) => Ok(Some(Secret::new(pem.expose_secret().clone()))),
+        (None, Some(path)) => fs::read_to_string(path).map(|pem| Some(Secret::new(pem))).map_err(|e| {
+            configuration_error(format!("JWT key {} can not be read from {}: {}", kid, path, e))
+        }),
+        (None, None) => Ok(None),
+    }
+}
+
+fn jwk(key: &JwtKeySettings, public_pem: &str) -> Result<Jwk, AppError> {
+    let public_key = RsaPublicKey::from_public_key_pem(public_pem)
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(public_pem))
+        .map_err(|e| configuration_error(format!("JWT key {} is not an RSA key: {}", key.kid, e)))?;
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(KeyAlgorithm::RS256),
+            key_id: Some(key.kid.clone()),
+            ..Default::default()
+        },
+        algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+            key_type: RSAKeyType::RSA,
+            n: URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+            e: URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+        }),
+    })
+}
+
+fn configuration_error(message: String) -> AppError {
+    AppError::new(
+        message.clone(),
+        AppErrorType::ConfigurationError(ConfigError::Message(message)),
+    )
+}
+
+fn token_error(e: jsonwebtoken::errors::Error) -> AppError {
+    AppError::new(
+        "Key parsing error.".to_string(),
+        AppErrorType::TokenEncodingError(e),
+    )
+}
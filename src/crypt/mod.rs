@@ -1,2 +1,7 @@
+pub mod api_token;
 pub mod hash;
+pub mod keys;
+pub mod refresh;
 pub mod token;
+pub mod totp;
+pub mod user_token;
@@ -0,0 +1,38 @@
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Opaque refresh token as stored, only the sha256 of the token is kept. \
+/// Tokens rotated from one login share a `family`, so reuse of a rotated token
+/// revokes every token of that login.
+#[derive(Debug, Clone, FromRow)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub family: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl RefreshToken {
+    // time to live, in minutes; returns the token to hand out and its stored form
+    pub fn new(user_id: Uuid, family: Option<Uuid>, ttl: i64) -> (String, Self) {
+        // 244 random bits from two v4 uuids
+        let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let refresh_token = Self {
+            id: Uuid::new_v4(),
+            family: family.unwrap_or_else(Uuid::new_v4),
+            user_id,
+            token_hash: hash_refresh_token(&token),
+            expires_at: Utc::now() + chrono::Duration::minutes(ttl),
+        };
+
+        (token, refresh_token)
+    }
+}
+
+// tokens are random enough for a fast unsalted hash, which also keeps them searchable
+pub fn hash_refresh_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
@@ -10,75 +10,204 @@ use axum_extra::{
     TypedHeader,
 };
 use chrono;
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use redis::{aio::ConnectionManager, AsyncCommands};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::{
+    crypt::{
+        api_token::{hash_api_token, ApiTokenScope, API_TOKEN_PREFIX},
+        keys::JwtKeys,
+    },
     errors::{AppError, AppErrorType},
     graphql::user::schema::SessionUser,
+    sql::api_token::{authenticate_api_token, AuthenticatedApiToken},
     startup::AppState,
 };
 
+pub static REVOKED_JTI_PREFIX: &str = "revoked_jti:";
+pub static REVOKED_SID_PREFIX: &str = "revoked_sid:";
+// audience of access tokens, user tokens carry their purpose instead
+pub static ACCESS_TOKEN_AUDIENCE: &str = "access";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub sub: String,
     pub exp: i64,
     pub iat: i64,
+    pub jti: String,
+    pub aud: String,
     pub email: String,
+    // session of the login, not set for api tokens and tokens issued before sessions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    // only set for api tokens, a login may do everything its user can
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rooms: Option<Vec<Uuid>>,
 }
 
 impl Claims {
     // time to live, in minutes
-    pub fn new(session_user: &SessionUser, ttl: i64) -> Self {
+    pub fn new(session_user: &SessionUser, sid: Uuid, ttl: i64) -> Self {
         let now = chrono::Utc::now();
 
         Self {
             sub: session_user.id.into(),
             exp: (now + chrono::Duration::minutes(ttl)).timestamp(),
             iat: now.timestamp(),
+            jti: Uuid::new_v4().to_string(),
+            aud: ACCESS_TOKEN_AUDIENCE.to_string(),
             email: session_user.email.to_string(),
+            sid: Some(sid),
+            scopes: None,
+            rooms: None,
+        }
+    }
+
+    // `jti` is the id of the api token, it never expires unless the token does
+    pub fn from_api_token(api_token: AuthenticatedApiToken) -> Self {
+        let token = api_token.token;
+
+        Self {
+            sub: token.user_id.into(),
+            exp: token.expires_at.map_or(i64::MAX, |expires_at| expires_at.timestamp()),
+            iat: token.created_at.timestamp(),
+            jti: token.id.into(),
+            aud: ACCESS_TOKEN_AUDIENCE.to_string(),
+            email: api_token.email,
+            sid: None,
+            scopes: Some(token.scopes),
+            rooms: token.room_ids,
+        }
+    }
+
+    pub fn is_api_token(&self) -> bool {
+        self.scopes.is_some()
+    }
+
+    pub fn require_scope(&self, scope: ApiTokenScope) -> Result<(), AppError> {
+        match &self.scopes {
+            Some(scopes) if !scopes.iter().any(|granted| granted == scope.as_str()) => {
+                Err(AppError::new(
+                    format!("Token {} lacks the {} scope.", self.jti, scope.as_str()),
+                    AppErrorType::ForbiddenError(format!("Missing scope {}", scope.as_str())),
+                ))
+            }
+            _ => Ok(()),
         }
     }
+
+    pub fn require_room(&self, room: Uuid) -> Result<(), AppError> {
+        match &self.rooms {
+            Some(rooms) if !rooms.contains(&room) => Err(AppError::new(
+                format!("Token {} is not allowed in room {}.", self.jti, room),
+                AppErrorType::ForbiddenError("Token is not allowed in this room".to_string()),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // account and token management is only done from a login
+    pub fn require_login(&self) -> Result<(), AppError> {
+        if self.is_api_token() {
+            return Err(AppError::new(
+                format!("Api token {} used for a login only operation.", self.jti),
+                AppErrorType::ForbiddenError("Not allowed with an api token".to_string()),
+            ));
+        }
+
+        Ok(())
+    }
+
+    // the user of a login, api tokens are denied unless the caller names the scope it needs
+    // through `scoped_user_id`, so anything new is login only until it opts in
+    pub fn user_id(&self) -> Result<Uuid, AppError> {
+        self.require_login()?;
+        self.subject()
+    }
+
+    pub fn scoped_user_id(&self, scope: ApiTokenScope) -> Result<Uuid, AppError> {
+        self.require_scope(scope)?;
+        self.subject()
+    }
+
+    fn subject(&self) -> Result<Uuid, AppError> {
+        Uuid::parse_str(&self.sub).map_err(|e| {
+            AppError::new(
+                "Token subject parse error.".to_string(),
+                AppErrorType::AuthorizationError(e.to_string()),
+            )
+        })
+    }
 }
 
-pub fn encode_token(claims: &Claims) -> Result<String, AppError> {
-    let encoding_key = &EncodingKey::from_rsa_pem(include_bytes!("../../keys/private.pem"))
+// deny-lists the token until it would have expired anyway
+pub async fn revoke_token(redis: &mut ConnectionManager, claims: &Claims) -> Result<(), AppError> {
+    let ttl = claims.exp - chrono::Utc::now().timestamp();
+    if ttl <= 0 {
+        return Ok(());
+    }
+
+    redis
+        .set_ex(format!("{}{}", REVOKED_JTI_PREFIX, claims.jti), 1, ttl as u64)
+        .await
         .map_err(|e| {
             AppError::new(
-                "Key encoding error.".to_string(),
-                AppErrorType::TokenEncodingError(e),
+                "Token revocation error.".to_string(),
+                AppErrorType::RedisError(e),
             )
-        })?;
-
-    encode(&Header::new(Algorithm::RS256), claims, &encoding_key).map_err(|e| {
-        AppError::new(
-            "Token encoding error.".to_string(),
-            AppErrorType::TokenEncodingError(e),
-        )
-    })
+        })
 }
 
-pub fn decode_token(token: &str) -> Result<Claims, AppError> {
-    let decoding_key =
-        DecodingKey::from_rsa_pem(include_bytes!("../../keys/public.pem")).map_err(|e| {
+// deny-lists every access token of the session, `ttl` is the access token time to live
+// in minutes, later tokens can not be issued as the session refresh tokens are revoked
+pub async fn revoke_session_tokens(
+    redis: &mut ConnectionManager,
+    sid: Uuid,
+    ttl: i64,
+) -> Result<(), AppError> {
+    redis
+        .set_ex(format!("{}{}", REVOKED_SID_PREFIX, sid), 1, (ttl * 60) as u64)
+        .await
+        .map_err(|e| {
             AppError::new(
-                "Key decoding error.".to_string(),
-                AppErrorType::TokenEncodingError(e),
+                "Session revocation error.".to_string(),
+                AppErrorType::RedisError(e),
             )
-        })?;
+        })
+}
 
-    decode::<Claims>(token, &decoding_key, &Validation::new(Algorithm::RS256))
-        .map(|v| v.claims)
+// the token itself or its whole session may be revoked
+pub async fn is_token_revoked(redis: &mut ConnectionManager, claims: &Claims) -> Result<bool, AppError> {
+    let mut keys = vec![format!("{}{}", REVOKED_JTI_PREFIX, claims.jti)];
+    if let Some(sid) = claims.sid {
+        keys.push(format!("{}{}", REVOKED_SID_PREFIX, sid));
+    }
+
+    redis
+        .exists(keys)
+        .await
+        .map(|revoked: u64| revoked > 0)
         .map_err(|e| {
             AppError::new(
-                "Token decoding error.".to_string(),
-                AppErrorType::TokenEncodingError(e),
+                "Token revocation check error.".to_string(),
+                AppErrorType::RedisError(e),
             )
         })
 }
 
-pub fn verify_token(token: Option<&str>) -> Result<Claims, AppError> {
-    decode_token(token.unwrap_or_default())
+pub fn encode_token(keys: &JwtKeys, claims: &Claims) -> Result<String, AppError> {
+    keys.encode(claims)
+}
+
+pub fn decode_token(keys: &JwtKeys, token: &str) -> Result<Claims, AppError> {
+    keys.decode(token, ACCESS_TOKEN_AUDIENCE)
+}
+
+pub fn verify_token(keys: &JwtKeys, token: Option<&str>) -> Result<Claims, AppError> {
+    decode_token(keys, token.unwrap_or_default())
 }
 
 pub fn get_auth_header_pair(token: String) -> (HeaderName, HeaderValue) {
@@ -89,21 +218,60 @@ pub fn get_auth_header_pair(token: String) -> (HeaderName, HeaderValue) {
     )
 }
 
+pub fn get_refresh_header_pair(token: String) -> (HeaderName, HeaderValue) {
+    (
+        HeaderName::from_lowercase(b"x-refresh-token").unwrap(),
+        HeaderValue::from_str(&token).unwrap(),
+    )
+}
+
+pub fn get_challenge_header_pair(token: String) -> (HeaderName, HeaderValue) {
+    (
+        HeaderName::from_lowercase(b"x-challenge-token").unwrap(),
+        HeaderValue::from_str(&token).unwrap(),
+    )
+}
+
+// claims of a bearer token, either an api token or a jwt that has not been revoked
+pub async fn authenticate(state: &AppState, token: &str) -> Result<Claims, AppError> {
+    if token.starts_with(API_TOKEN_PREFIX) {
+        return authenticate_api_token(&state.pool, &hash_api_token(token))
+            .await?
+            .map(Claims::from_api_token)
+            .ok_or_else(|| {
+                AppError::new(
+                    "Api token is unknown, revoked or expired.".to_string(),
+                    AppErrorType::AuthorizationError("Invalid api token".to_string()),
+                )
+            });
+    }
+
+    let claims = decode_token(&state.keys, token)?;
+    if is_token_revoked(&mut state.redis.clone(), &claims).await? {
+        return Err(AppError::new(
+            format!("Token {} is revoked.", claims.jti),
+            AppErrorType::AuthorizationError("Token is revoked".to_string()),
+        ));
+    }
+
+    Ok(claims)
+}
+
 #[async_trait]
 impl FromRequestParts<AppState> for Claims {
     type Rejection = AppError;
 
     async fn from_request_parts(
         parts: &mut Parts,
-        _state: &AppState,
+        state: &AppState,
     ) -> Result<Self, Self::Rejection> {
         match parts.extract::<TypedHeader<Authorization<Bearer>>>().await {
-            Ok(TypedHeader(Authorization(bearer))) => decode_token(bearer.token()),
+            Ok(TypedHeader(Authorization(bearer))) => authenticate(state, bearer.token()).await,
             Err(e) => Err(AppError::new(
                 e.to_string(),
                 AppErrorType::AuthorizationError(format!(
                     "No authorization header is present: {}",
-                    e.to_string()
+                    e
                 )),
             )),
         }
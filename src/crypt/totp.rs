@@ -0,0 +1,74 @@
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+use crate::errors::{AppError, AppErrorType};
+
+pub static TOTP_STEP: u64 = 30;
+pub static RECOVERY_CODE_COUNT: usize = 10;
+
+// base32, as shown in authenticator apps
+pub fn generate_totp_secret() -> String {
+    match Secret::generate_secret().to_encoded() {
+        Secret::Encoded(secret) => secret,
+        Secret::Raw(_) => unreachable!("Encoded secrets are never raw."),
+    }
+}
+
+pub fn totp(secret: &str, issuer: &str, account: &str) -> Result<TOTP, AppError> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().map_err(|e| {
+        AppError::new(
+            format!("TOTP secret parse error: {:?}", e),
+            AppErrorType::InternalServerError,
+        )
+    })?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        TOTP_STEP,
+        secret,
+        Some(issuer.to_string()),
+        account.to_string(),
+    )
+    .map_err(|e| {
+        AppError::new(
+            format!("TOTP setup error: {:?}", e),
+            AppErrorType::InternalServerError,
+        )
+    })
+}
+
+// time step the code belongs to, one step of clock skew is accepted either way
+pub fn matching_step(totp: &TOTP, code: &str) -> Option<i64> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let step = now / TOTP_STEP;
+
+    [step - 1, step, step + 1]
+        .into_iter()
+        .find(|step| totp.generate(step * TOTP_STEP) == code)
+        .map(|step| step as i64)
+}
+
+// 64 random bits each, formatted as xxxx-xxxx-xxxx-xxxx
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            // the first 8 hex digits of a v4 uuid are fully random
+            let first = Uuid::new_v4().simple().to_string();
+            let second = Uuid::new_v4().simple().to_string();
+            format!(
+                "{}-{}-{}-{}",
+                &first[0..4],
+                &first[4..8],
+                &second[0..4],
+                &second[4..8]
+            )
+        })
+        .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.trim().to_lowercase().as_bytes()))
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{crypt::keys::JwtKeys, errors::AppError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+    TwoFactorLogin,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::VerifyEmail => "verify_email",
+            TokenPurpose::ResetPassword => "reset_password",
+            TokenPurpose::TwoFactorLogin => "two_factor_login",
+        }
+    }
+}
+
+/// Signed single-use token sent by email or handed out between login steps,
+/// `jti` is the id of its `user_tokens` row which records whether it was used. \
+/// `aud` is the purpose, so it is never accepted as an access token or for another purpose.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserTokenClaims {
+    pub sub: Uuid,
+    pub jti: Uuid,
+    pub aud: String,
+    pub purpose: TokenPurpose,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+impl UserTokenClaims {
+    // time to live, in minutes
+    pub fn new(user_id: Uuid, purpose: TokenPurpose, ttl: i64) -> Self {
+        let now = chrono::Utc::now();
+
+        Self {
+            sub: user_id,
+            jti: Uuid::new_v4(),
+            aud: purpose.as_str().to_string(),
+            purpose,
+            iat: now.timestamp(),
+            exp: (now + chrono::Duration::minutes(ttl)).timestamp(),
+        }
+    }
+
+    pub fn expires_at(&self) -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::from_timestamp(self.exp, 0).unwrap_or_else(chrono::Utc::now)
+    }
+
+    pub fn encode(&self, keys: &JwtKeys) -> Result<String, AppError> {
+        keys.encode(self)
+    }
+
+    pub fn decode(keys: &JwtKeys, token: &str, purpose: TokenPurpose) -> Result<Self, AppError> {
+        keys.decode(token, purpose.as_str())
+    }
+}
@@ -31,3 +31,12 @@ pub async fn init_redis_connection(
         })
         .await
 }
+
+pub fn init_redis_client(connection_string: &str) -> Result<redis::Client, AppError> {
+    redis::Client::open(connection_string).map_err(|e| {
+        AppError::new(
+            "Redis client initialization error.".to_string(),
+            AppErrorType::RedisError(e),
+        )
+    })
+}
@@ -1,11 +1,11 @@
 use axum::{
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use bcrypt::BcryptError;
 use config::ConfigError;
-use juniper::{FieldError, IntoFieldError, Value};
+use juniper::{FieldError, IntoFieldError, Object, Value};
 use redis::RedisError;
 use serde_json::json;
 use sqlx;
@@ -27,21 +27,45 @@ pub enum AppErrorType {
     #[error("Password encoding error occured: {0}.")]
     EncodingError(BcryptError),
 
+    #[error("Password hashing error occured: {0}.")]
+    HashingError(argon2::password_hash::Error),
+
     #[error("Password is wrong.")]
     PasswordWrongError,
 
     #[error("Authorization error occured: {0}.")] // pass jwt errors
     AuthorizationError(String),
 
+    #[error("Forbidden: {0}.")]
+    ForbiddenError(String),
+
+    #[error("Account is locked for {0} seconds.")]
+    AccountLocked(u64),
+
     #[error("User not found.")]
     UserNotFound,
 
+    #[error("Room not found.")]
+    RoomNotFound,
+
+    #[error("Message not found.")]
+    MessageNotFound,
+
     #[error("Validation error occured: {0}.")]
     ValidationError(String),
 
+    #[error("Validation errors occured: {}", .0.join(" "))]
+    ValidationErrors(Vec<String>),
+
     #[error("Internal server error occured.")]
     InternalServerError,
 
+    #[error("Identity provider error occured: {0}.")]
+    IdentityProviderError(String),
+
+    #[error("Mail error occured: {0}.")]
+    MailError(String),
+
     #[error("Redis error occured.")]
     RedisError(RedisError),
 
@@ -55,14 +79,16 @@ pub struct AppError {
     error_type: AppErrorType,
 }
 
-impl AppError {
-    pub fn default() -> Self {
+impl Default for AppError {
+    fn default() -> Self {
         AppError {
             error_type: AppErrorType::InternalServerError,
             message: Some("Error.".to_string()),
         }
     }
+}
 
+impl AppError {
     pub fn new(message: String, error_type: AppErrorType) -> Self {
         AppError {
             message: Some(message),
@@ -76,6 +102,17 @@ impl AppError {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        // every failed rule is listed separately as well
+        let errors = match &self.error_type {
+            AppErrorType::ValidationErrors(errors) => Some(errors.clone()),
+            _ => None,
+        };
+
+        let retry_after = match &self.error_type {
+            AppErrorType::AccountLocked(seconds) => Some(*seconds),
+            _ => None,
+        };
+
         let (status, error_message) = match self {
             AppError {
                 error_type: AppErrorType::ConfigurationError(config_error),
@@ -105,6 +142,13 @@ impl IntoResponse for AppError {
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("Encryption error. {}", bcrypt_error),
             ),
+            AppError {
+                error_type: AppErrorType::HashingError(hash_error),
+                ..
+            } => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Encryption error. {}", hash_error),
+            ),
             AppError {
                 error_type: AppErrorType::PasswordWrongError,
                 message,
@@ -116,6 +160,20 @@ impl IntoResponse for AppError {
                 error_type: AppErrorType::AuthorizationError(error),
                 ..
             } => (StatusCode::UNAUTHORIZED, format!("Unauthorized. {}", error)),
+            AppError {
+                error_type: AppErrorType::ForbiddenError(error),
+                ..
+            } => (StatusCode::FORBIDDEN, format!("Forbidden. {}", error)),
+            AppError {
+                error_type: AppErrorType::AccountLocked(seconds),
+                ..
+            } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "Too many failed login attempts. Try again in {} seconds.",
+                    seconds
+                ),
+            ),
             AppError {
                 error_type: AppErrorType::UserNotFound,
                 message,
@@ -123,6 +181,20 @@ impl IntoResponse for AppError {
                 StatusCode::NOT_FOUND,
                 format!("User not found. {}", message.unwrap()),
             ),
+            AppError {
+                error_type: AppErrorType::RoomNotFound,
+                message,
+            } => (
+                StatusCode::NOT_FOUND,
+                format!("Room not found. {}", message.unwrap()),
+            ),
+            AppError {
+                error_type: AppErrorType::MessageNotFound,
+                message,
+            } => (
+                StatusCode::NOT_FOUND,
+                format!("Message not found. {}", message.unwrap()),
+            ),
             AppError {
                 error_type: AppErrorType::ValidationError(error),
                 ..
@@ -130,6 +202,13 @@ impl IntoResponse for AppError {
                 StatusCode::BAD_REQUEST,
                 format!("Validation error. {}", error),
             ),
+            AppError {
+                error_type: AppErrorType::ValidationErrors(errors),
+                ..
+            } => (
+                StatusCode::BAD_REQUEST,
+                format!("Validation error. {}", errors.join(" ")),
+            ),
             AppError {
                 error_type: AppErrorType::InternalServerError,
                 message,
@@ -137,28 +216,51 @@ impl IntoResponse for AppError {
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("Internal server error. {}", message.unwrap()),
             ),
+            AppError {
+                error_type: AppErrorType::IdentityProviderError(error),
+                ..
+            } => (
+                StatusCode::BAD_GATEWAY,
+                format!("Identity provider error. {}", error),
+            ),
+            AppError {
+                error_type: AppErrorType::MailError(error),
+                ..
+            } => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Mail error. {}", error),
+            ),
             AppError {
                 error_type: AppErrorType::RedisError(error),
                 ..
             } => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Redis error. {}", error.to_string()),
+                format!("Redis error. {}", error),
             ),
             AppError {
                 error_type: AppErrorType::TracingError(error),
                 ..
             } => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Tracing subscriber error. {}", error.to_string()),
+                format!("Tracing subscriber error. {}", error),
             ),
         };
 
-        let body = Json(json!({
-            "error": error_message,
-        }));
+        let body = match errors {
+            Some(errors) => Json(json!({
+                "error": error_message,
+                "errors": errors,
+            })),
+            None => Json(json!({
+                "error": error_message,
+            })),
+        };
 
         // its often easiest to implement `IntoResponse` by calling other implementations
-        (status, body).into_response()
+        match retry_after {
+            Some(seconds) => (status, [(RETRY_AFTER, seconds.to_string())], body).into_response(),
+            None => (status, body).into_response(),
+        }
     }
 }
 
@@ -171,6 +273,18 @@ impl fmt::Display for AppError {
 impl IntoFieldError for AppError {
     fn into_field_error(self) -> FieldError {
         // improve extensions, add path, etc. for gql
-        FieldError::new(self.message(), Value::Null)
+        let extensions = match &self.error_type {
+            AppErrorType::ValidationErrors(errors) => {
+                let mut extensions = Object::with_capacity(1);
+                extensions.add_field(
+                    "errors",
+                    Value::list(errors.iter().map(|e| Value::scalar(e.clone())).collect()),
+                );
+                Value::object(extensions)
+            }
+            _ => Value::Null,
+        };
+
+        FieldError::new(self.message(), extensions)
     }
 }
@@ -0,0 +1,88 @@
+use juniper::{GraphQLInputObject, GraphQLObject};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    crypt::api_token::ApiTokenScope,
+    errors::{AppError, AppErrorType},
+};
+
+pub static MAX_API_TOKEN_NAME_LENGTH: usize = 255;
+
+/// `scopes` - any of `messages:read`, `messages:write`, `rooms:read`, `rooms:write` and
+/// `metrics:read` \
+/// `room_ids` - rooms the token is restricted to, every room of the user when not set \
+/// `expires_in_days` - the token never expires when not set \
+/// `bot_id` - admin only, issues the token for a bot instead of the caller
+#[derive(Serialize, Deserialize, GraphQLInputObject, Debug)]
+pub struct ApiTokenInput {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub room_ids: Option<Vec<Uuid>>,
+    pub expires_in_days: Option<i32>,
+    pub bot_id: Option<Uuid>,
+}
+
+impl ApiTokenInput {
+    pub fn validate_api_token_input(self) -> Result<Self, AppError> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() || name.chars().count() > MAX_API_TOKEN_NAME_LENGTH {
+            return Err(AppError::new(
+                format!("Api token name {} is invalid.", name),
+                AppErrorType::ValidationError(format!(
+                    "Token name must be between 1 and {} characters.",
+                    MAX_API_TOKEN_NAME_LENGTH
+                )),
+            ));
+        }
+
+        if self.scopes.is_empty() {
+            return Err(AppError::new(
+                "Api token has no scopes.".to_string(),
+                AppErrorType::ValidationError("At least one scope is required.".to_string()),
+            ));
+        }
+        let mut scopes = Vec::new();
+        for scope in self.scopes {
+            let scope = ApiTokenScope::parse(scope.trim())?.as_str().to_string();
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+
+        if self.expires_in_days.is_some_and(|days| days < 1) {
+            return Err(AppError::new(
+                "Api token expiry is not positive.".to_string(),
+                AppErrorType::ValidationError("Expiry must be at least one day.".to_string()),
+            ));
+        }
+
+        Ok(ApiTokenInput {
+            name,
+            scopes,
+            ..self
+        })
+    }
+}
+
+/// Long-lived token of a user or a bot, only the sha256 of the token is kept.
+#[derive(Serialize, Deserialize, GraphQLObject, Debug, Clone, FromRow)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub room_ids: Option<Vec<Uuid>>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub revoked_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// `token` - shown once, only its hash is stored
+#[derive(GraphQLObject, Debug)]
+pub struct CreatedApiToken {
+    pub token: String,
+    pub api_token: ApiToken,
+}
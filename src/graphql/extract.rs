@@ -0,0 +1,46 @@
+use axum::{
+    async_trait,
+    extract::{FromRequest, Request},
+    http::header::CONTENT_TYPE,
+    Form, Json,
+};
+use serde::de::DeserializeOwned;
+
+use crate::errors::{AppError, AppErrorType};
+
+/// Body deserialized from `application/json` when the request says so,
+/// from `application/x-www-form-urlencoded` otherwise.
+pub struct FormOrJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for FormOrJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("application/json"));
+
+        let body = if is_json {
+            Json::<T>::from_request(req, state)
+                .await
+                .map(|Json(body)| body)
+                .map_err(|e| e.body_text())
+        } else {
+            Form::<T>::from_request(req, state)
+                .await
+                .map(|Form(body)| body)
+                .map_err(|e| e.body_text())
+        };
+
+        body.map(FormOrJson).map_err(|e| {
+            AppError::new(e.clone(), AppErrorType::ValidationError(e))
+        })
+    }
+}
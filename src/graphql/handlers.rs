@@ -1,45 +1,94 @@
 use axum::{
-    extract::State,
+    extract::{ws::WebSocketUpgrade, Query, State},
     http::StatusCode,
-    response::{Html, IntoResponse, Response},
-    Form, Json,
+    response::{Html, IntoResponse, Redirect, Response},
+    Json,
 };
 use axum_macros::debug_handler;
-use juniper::http::{graphiql::graphiql_source, GraphQLRequest};
+use jsonwebtoken::jwk::JwkSet;
+use juniper::{
+    http::{graphiql::graphiql_source, GraphQLRequest},
+    Variables,
+};
+use juniper_axum::subscriptions::serve_graphql_transport_ws;
+use juniper_graphql_ws::ConnectionConfig;
 use secrecy::Secret;
 use serde::{Deserialize, Serialize};
-use tracing::instrument;
+use std::time::Duration;
+use tracing::{info, instrument, warn};
 
 use crate::{
-    configuration,
+    configuration::{self, OidcSettings, Settings},
     crypt::{
-        hash::verify_password,
-        token::{encode_token, get_auth_header_pair, Claims},
+        hash::{hash_password, verify_password},
+        refresh::{hash_refresh_token, RefreshToken},
+        token::{
+            authenticate, encode_token, get_auth_header_pair, get_challenge_header_pair,
+            get_refresh_header_pair, revoke_token, Claims,
+        },
     },
-    errors::AppError,
+    errors::{AppError, AppErrorType},
     graphql::{
+        extract::FormOrJson,
         root::GraphQLContext,
         user::schema::{SessionUser, UserInput},
     },
-    sql::user::{get_user, insert_user},
+    service::{
+        account::{request_password_reset, reset_password, send_verification_email, verify_email},
+        oidc::{self, OidcClient},
+        session::{end_sessions, SessionOrigin},
+        throttle::LoginThrottle,
+        two_factor::{complete_login_challenge, issue_login_challenge},
+    },
+    sql::{
+        refresh_token::{
+            insert_refresh_token, revoke_refresh_token, rotate_refresh_token, Rotation,
+        },
+        session::{insert_session, revoke_session, touch_session},
+        user::{get_user, get_user_by_id, insert_user, update_password},
+    },
     startup::AppState,
 };
 
 use super::user::schema::User;
 
+static GRAPHQL_WS_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
 #[derive(Serialize, Deserialize)]
 pub struct LoginForm {
     pub email: String,
     pub password: String,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct RefreshForm {
+    pub refresh_token: String,
+}
+
+/// Body of every response starting or renewing a session, `expires_in` is in seconds.
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub refresh_token: String,
+    pub user: User,
+}
+
+/// Body of a login waiting for its two-factor code, `expires_in` is in seconds.
+#[derive(Serialize)]
+pub struct ChallengeResponse {
+    pub challenge_token: String,
+    pub expires_in: i64,
+}
+
 #[debug_handler]
 pub async fn graphql(
     State(data): State<AppState>,
     claims: Claims,
     Json(graphql_req): Json<GraphQLRequest>,
 ) -> impl IntoResponse {
-    let context = GraphQLContext::new(data.pool.clone(), claims);
+    let context = GraphQLContext::new(&data, claims);
     let res = graphql_req.execute(&data.schema, &context).await;
 
     let json = serde_json::to_string(&res).unwrap();
@@ -53,8 +102,46 @@ pub async fn graphql(
     (status, json)
 }
 
+// public keys other services verify our tokens with
+pub async fn jwks(State(data): State<AppState>) -> Json<JwkSet> {
+    Json(data.keys.jwks().clone())
+}
+
+// graphql-transport-ws, browsers can not set headers on websockets so the access or api token
+// is sent as `Authorization` in the `connection_init` payload
+#[debug_handler]
+pub async fn graphql_ws(ws: WebSocketUpgrade, State(data): State<AppState>) -> Response {
+    let schema = data.schema.clone();
+    let init = move |params: Variables| async move {
+        let token = ["Authorization", "authorization"]
+            .into_iter()
+            .find_map(|key| params.get(key))
+            .and_then(|value| value.as_string_value())
+            .map(|value| value.trim_start_matches("Bearer ").to_string())
+            .ok_or_else(|| {
+                AppError::new(
+                    "No authorization in connection_init.".to_string(),
+                    AppErrorType::AuthorizationError(
+                        "No authorization in connection_init".to_string(),
+                    ),
+                )
+            })?;
+        let claims = authenticate(&data, &token).await?;
+
+        Ok::<_, AppError>(
+            ConnectionConfig::new(GraphQLContext::new(&data, claims))
+                .with_keep_alive_interval(GRAPHQL_WS_KEEP_ALIVE),
+        )
+    };
+
+    // the upgrade future is the one of juniper_axum, an async block around it is not `Send`
+    // for every lifetime of the schema
+    ws.protocols(["graphql-transport-ws"])
+        .on_upgrade(move |socket| serve_graphql_transport_ws(socket, schema, init))
+}
+
 pub async fn playground() -> Html<String> {
-    let html = graphiql_source("/graphql", None);
+    let html = graphiql_source("/graphql", Some("/graphql/ws"));
     Html(html)
 }
 
@@ -62,35 +149,316 @@ pub async fn playground() -> Html<String> {
 #[debug_handler]
 pub async fn login(
     State(data): State<AppState>,
-    Form(form): Form<LoginForm>,
-) -> Result<Response<String>, AppError> {
-    let user = get_user(&data.pool, &form.email).await?;
+    origin: SessionOrigin,
+    FormOrJson(form): FormOrJson<LoginForm>,
+) -> Result<Response, AppError> {
+    let configuration = configuration::get_configuration()?;
+
+    let mut throttle = LoginThrottle::new(data.redis.clone(), &configuration.login_throttle);
+    throttle.check(&form.email, origin.ip).await?;
+
+    // unknown emails count as failed attempts as well
+    let verified = match get_user(&data.pool, &form.email).await {
+        Ok(user) => verify_password(
+            Secret::new(form.password.clone()),
+            &user.password,
+            &configuration.argon2,
+        )
+        .map(|needs_rehash| (user, needs_rehash)),
+        Err(e) => Err(e),
+    };
+    let (user, needs_rehash) = match verified {
+        Ok(verified) => verified,
+        Err(e) => {
+            throttle.record_failure(&form.email, origin.ip).await?;
+            return Err(e);
+        }
+    };
+    // with totp the counter is only cleared once the code is right as well
+    if user.totp_enabled_at.is_none() {
+        throttle.record_success(&form.email).await?;
+    }
+
+    // legacy bcrypt or outdated argon2 parameters, a failed upgrade does not fail the login
+    if needs_rehash {
+        match hash_password(form.password, &configuration.argon2) {
+            Ok(hash) => {
+                if let Err(e) = update_password(&data.pool, user.id, hash).await {
+                    warn!("Password rehash of user {} failed: {}", user.id, e);
+                }
+            }
+            Err(e) => warn!("Password rehash of user {} failed: {}", user.id, e),
+        }
+    }
+
+    finish_login(&data, &user, &configuration, &origin).await
+}
+
+// a verified first factor either opens the session or, with totp enabled, issues the challenge
+async fn finish_login(
+    data: &AppState,
+    user: &User,
+    configuration: &Settings,
+    origin: &SessionOrigin,
+) -> Result<Response, AppError> {
+    if configuration.mail.require_verified_email && user.verified_at.is_none() {
+        return Err(AppError::new(
+            format!("Email of user {} is not verified.", user.id),
+            AppErrorType::ForbiddenError("Email is not verified".to_string()),
+        ));
+    }
+
+    // the session is only issued by the second step
+    if user.totp_enabled_at.is_some() {
+        let challenge =
+            issue_login_challenge(&data.pool, &data.keys, &configuration.two_factor, user).await?;
+        let challenge_header_pair = get_challenge_header_pair(challenge.clone());
+
+        return Ok((
+            StatusCode::ACCEPTED,
+            [challenge_header_pair],
+            Json(ChallengeResponse {
+                challenge_token: challenge,
+                expires_in: configuration.two_factor.challenge_max_age * 60,
+            }),
+        )
+            .into_response());
+    }
+
+    start_session(data, user, configuration, origin).await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TwoFactorLoginForm {
+    pub challenge_token: String,
+    pub code: String,
+}
+
+// second login step, exchanges the challenge token and a totp or recovery code for the session
+#[instrument(name = "Two-factor login.", skip(data, form))]
+#[debug_handler]
+pub async fn login_two_factor(
+    State(data): State<AppState>,
+    origin: SessionOrigin,
+    FormOrJson(form): FormOrJson<TwoFactorLoginForm>,
+) -> Result<Response, AppError> {
+    let configuration = configuration::get_configuration()?;
+
+    let mut throttle = LoginThrottle::new(data.redis.clone(), &configuration.login_throttle);
+    let user = complete_login_challenge(
+        &data.pool,
+        &data.keys,
+        &configuration.two_factor,
+        &mut throttle,
+        origin.ip,
+        &form.challenge_token,
+        &form.code,
+    )
+    .await?;
+
+    start_session(&data, &user, &configuration, &origin).await
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: String,
+    pub state: String,
+}
+
+// redirects to the identity provider
+#[instrument(name = "Oidc login.", skip(data))]
+#[debug_handler]
+pub async fn oidc_login(State(data): State<AppState>) -> Result<Redirect, AppError> {
+    let configuration = configuration::get_configuration()?;
+    let settings = oidc_settings(&configuration)?;
+
+    let url = OidcClient::new(settings, data.redis.clone())
+        .authorization_url()
+        .await?;
+
+    Ok(Redirect::to(&url))
+}
+
+// the provider redirects back here, signs in the linked user the same way a password login does
+#[instrument(name = "Oidc callback.", skip(data, query))]
+#[debug_handler]
+pub async fn oidc_callback(
+    State(data): State<AppState>,
+    origin: SessionOrigin,
+    Query(query): Query<OidcCallbackQuery>,
+) -> Result<Response, AppError> {
+    let configuration = configuration::get_configuration()?;
+    let settings = oidc_settings(&configuration)?;
+
+    let claims = OidcClient::new(settings, data.redis.clone())
+        .exchange(&query.code, &query.state)
+        .await?;
+    let user = oidc::sign_in(&data.pool, &configuration.argon2, claims).await?;
+
+    finish_login(&data, &user, &configuration, &origin).await
+}
+
+fn oidc_settings(configuration: &Settings) -> Result<&OidcSettings, AppError> {
+    configuration.oidc.as_ref().ok_or_else(|| {
+        AppError::new(
+            "Oidc is not configured.".to_string(),
+            AppErrorType::ForbiddenError("Sign-in with an identity provider is disabled".to_string()),
+        )
+    })
+}
+
+// the session id is the `sid` of the access tokens and the family of the refresh tokens
+async fn start_session(
+    data: &AppState,
+    user: &User,
+    configuration: &Settings,
+    origin: &SessionOrigin,
+) -> Result<Response, AppError> {
+    let sid = insert_session(&data.pool, user.id, origin).await?;
+    let session_user = SessionUser {
+        id: user.id,
+        email: &user.email,
+    };
+
+    let token = encode_token(
+        &data.keys,
+        &Claims::new(&session_user, sid, configuration.token_max_age),
+    )?;
+
+    let (refresh_token, stored) =
+        RefreshToken::new(user.id, Some(sid), configuration.refresh_token_max_age);
+    insert_refresh_token(&data.pool, &stored).await?;
 
-    verify_password(Secret::new(form.password), &user.password)?;
+    Ok(token_response(token, refresh_token, configuration, user.clone()))
+}
+
+// rotates the refresh token, the presented one can not be used again
+#[instrument(name = "Refreshing a token.", skip(data, form))]
+#[debug_handler]
+pub async fn refresh_token(
+    State(data): State<AppState>,
+    FormOrJson(form): FormOrJson<RefreshForm>,
+) -> Result<Response, AppError> {
+    let configuration = configuration::get_configuration()?;
 
+    let rotation = rotate_refresh_token(
+        &data.pool,
+        &hash_refresh_token(&form.refresh_token),
+        configuration.refresh_token_max_age,
+    )
+    .await?;
+    let (refresh_token, stored) = match rotation {
+        Rotation::Rotated(refresh_token, stored) => (refresh_token, stored),
+        // a reused token may be stolen, the access tokens of its session stop working too
+        Rotation::Reused { family, user_id } => {
+            end_sessions(
+                &data.pool,
+                &mut data.redis.clone(),
+                &data.chats,
+                user_id,
+                vec![family],
+                configuration.token_max_age,
+            )
+            .await?;
+            return Err(invalid_refresh_token());
+        }
+        Rotation::Rejected => return Err(invalid_refresh_token()),
+    };
+
+    touch_session(&data.pool, stored.family).await?;
+
+    let user = get_user_by_id(&data.pool, stored.user_id).await?;
     let session_user = SessionUser {
         id: user.id,
         email: &user.email,
     };
 
-    let ttl = configuration::get_configuration()?.token_max_age;
+    let token = encode_token(
+        &data.keys,
+        &Claims::new(&session_user, stored.family, configuration.token_max_age),
+    )?;
+
+    Ok(token_response(token, refresh_token, &configuration, user))
+}
+
+fn invalid_refresh_token() -> AppError {
+    AppError::new(
+        "Refresh token is revoked, expired or unknown.".to_string(),
+        AppErrorType::AuthorizationError("Invalid refresh token".to_string()),
+    )
+}
+
+// revokes the access token in use and the refresh tokens of its login
+#[instrument(name = "Logout.", skip(data, claims, form), fields(claims.sub = %claims.sub))]
+#[debug_handler]
+pub async fn logout(
+    State(data): State<AppState>,
+    claims: Claims,
+    FormOrJson(form): FormOrJson<RefreshForm>,
+) -> Result<Response<String>, AppError> {
+    let user_id = claims.user_id()?;
+
+    revoke_token(&mut data.redis.clone(), &claims).await?;
 
-    let token = encode_token(&Claims::new(&session_user, ttl))?;
+    // logging out twice is not an error
+    if !revoke_refresh_token(&data.pool, &hash_refresh_token(&form.refresh_token), user_id).await? {
+        info!("Refresh token of user {} is unknown or already revoked.", user_id);
+    }
 
-    let auth_header_pair = get_auth_header_pair(token);
+    if let Some(sid) = claims.sid {
+        if revoke_session(&data.pool, user_id, sid).await? {
+            let configuration = configuration::get_configuration()?;
+            end_sessions(
+                &data.pool,
+                &mut data.redis.clone(),
+                &data.chats,
+                user_id,
+                vec![sid],
+                configuration.token_max_age,
+            )
+            .await?;
+        }
+    }
 
     Ok(Response::builder()
-        .header(auth_header_pair.0, auth_header_pair.1)
-        .body("Authorized".to_string())
+        .body("Logged out".to_string())
         .unwrap())
 }
 
+// the tokens are set as headers as well, for clients reading them from there
+fn token_response(
+    token: String,
+    refresh_token: String,
+    configuration: &Settings,
+    user: User,
+) -> Response {
+    let auth_header_pair = get_auth_header_pair(token.clone());
+    let refresh_header_pair = get_refresh_header_pair(refresh_token.clone());
+
+    (
+        [auth_header_pair, refresh_header_pair],
+        Json(TokenResponse {
+            access_token: token,
+            token_type: "Bearer".to_string(),
+            expires_in: configuration.token_max_age * 60,
+            refresh_token,
+            user,
+        }),
+    )
+        .into_response()
+}
+
 pub async fn register(
     State(data): State<AppState>,
-    form: Form<UserInput>,
-) -> Result<Response<String>, AppError> {
+    FormOrJson(form): FormOrJson<UserInput>,
+) -> Result<Response, AppError> {
     // Validate user input
-    let user_input = UserInput::validate_user_input(form.0)?;
+    let configuration = configuration::get_configuration()?;
+    let user_input = UserInput::validate_user_input(
+        form,
+        &configuration.password_policy,
+        &configuration.argon2,
+    )?;
     let user = User {
         name: user_input.name,
         email: user_input.email,
@@ -98,9 +466,89 @@ pub async fn register(
         ..Default::default()
     };
     
-    insert_user(&data.pool, user).await.map(|_| {
-        Response::builder()
-            .body("User created successfully.".to_string())
-            .unwrap()
-    })
+    let user = insert_user(&data.pool, user).await?;
+
+    // the user can ask for another one, a failed email does not fail the registration
+    if let Err(e) = send_verification_email(
+        &data.pool,
+        &data.keys,
+        data.mailer.as_ref(),
+        &configuration.mail,
+        &user,
+    )
+    .await
+    {
+        warn!("Verification email of user {} failed: {}", user.id, e);
+    }
+
+    Ok((StatusCode::CREATED, Json(user)).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PasswordResetRequestForm {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PasswordResetForm {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EmailVerificationForm {
+    pub token: String,
+}
+
+#[instrument(name = "Requesting a password reset.", skip(data, form))]
+pub async fn request_password_reset_handler(
+    State(data): State<AppState>,
+    FormOrJson(form): FormOrJson<PasswordResetRequestForm>,
+) -> Result<Response<String>, AppError> {
+    let configuration = configuration::get_configuration()?;
+    request_password_reset(
+        &data.pool,
+        &data.keys,
+        data.mailer.as_ref(),
+        &configuration.mail,
+        &form.email,
+    )
+    .await?;
+
+    Ok(Response::builder()
+        .body("If the account exists, a password reset email was sent.".to_string())
+        .unwrap())
+}
+
+#[instrument(name = "Resetting a password.", skip(data, form))]
+pub async fn reset_password_handler(
+    State(data): State<AppState>,
+    FormOrJson(form): FormOrJson<PasswordResetForm>,
+) -> Result<Response<String>, AppError> {
+    let configuration = configuration::get_configuration()?;
+    reset_password(
+        &data,
+        &form.token,
+        form.password,
+        &configuration.password_policy,
+        &configuration.argon2,
+        configuration.token_max_age,
+    )
+    .await?;
+
+    Ok(Response::builder()
+        .body("Password changed.".to_string())
+        .unwrap())
+}
+
+#[instrument(name = "Verifying an email.", skip(data, form))]
+pub async fn verify_email_handler(
+    State(data): State<AppState>,
+    FormOrJson(form): FormOrJson<EmailVerificationForm>,
+) -> Result<Response<String>, AppError> {
+    verify_email(&data.pool, &data.keys, &form.token).await?;
+
+    Ok(Response::builder()
+        .body("Email verified.".to_string())
+        .unwrap())
 }
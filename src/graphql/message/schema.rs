@@ -0,0 +1,200 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use juniper::GraphQLObject;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, AppErrorType},
+    ws::schema::{MessageAuthor, MessageStatus, SocketMessageContent},
+};
+
+pub static DEFAULT_PAGE_SIZE: i32 = 50;
+pub static MAX_PAGE_SIZE: i32 = 100;
+
+/// Position of a message in the (created_at, id) ordering of a room.
+#[derive(Debug, Clone)]
+pub struct MessageCursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl MessageCursor {
+    pub fn encode(&self) -> String {
+        format!(
+            "{}|{}",
+            self.created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.id
+        )
+    }
+
+    pub fn parse(cursor: &str) -> Result<Self, AppError> {
+        let error = || {
+            AppError::new(
+                format!("{} is not a valid cursor.", cursor),
+                AppErrorType::ValidationError(format!("{} is not a valid cursor.", cursor)),
+            )
+        };
+
+        let (created_at, id) = cursor.split_once('|').ok_or_else(error)?;
+
+        Ok(Self {
+            created_at: DateTime::parse_from_rfc3339(created_at)
+                .map_err(|_| error())?
+                .with_timezone(&Utc),
+            id: Uuid::parse_str(id).map_err(|_| error())?,
+        })
+    }
+}
+
+/// Older pages follow `after`, newer pages precede `before`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageDirection {
+    Older,
+    Newer,
+}
+
+#[derive(GraphQLObject, Debug, Clone)]
+pub struct Message {
+    pub id: Uuid,
+    pub content: String,
+    pub author: MessageAuthor,
+    pub room: Uuid,
+    pub status: MessageStatus,
+    pub created_at: DateTime<Utc>,
+    pub parent_id: Option<Uuid>,
+    pub reply_count: i32,
+    pub last_reply_at: Option<DateTime<Utc>>,
+    pub reactions: Vec<ReactionCount>,
+    pub seen_by: Vec<MessageAuthor>,
+}
+
+/// Reactions with one emoji on a message \
+/// `reacted_by_me` - whether the requesting user is one of them
+#[derive(GraphQLObject, Debug, Clone, FromRow)]
+pub struct ReactionCount {
+    #[graphql(ignore)]
+    pub message_id: Uuid,
+    pub emoji: String,
+    pub count: i32,
+    pub reacted_by_me: bool,
+}
+
+/// User whose read cursor is at or past a message.
+#[derive(Debug, Clone, FromRow)]
+pub struct MessageReader {
+    pub message_id: Uuid,
+    #[sqlx(flatten)]
+    pub user: MessageAuthor,
+}
+
+impl From<SocketMessageContent> for Message {
+    fn from(message: SocketMessageContent) -> Self {
+        Message {
+            id: message.id,
+            content: message.content,
+            author: message.author,
+            room: message.room,
+            status: message.status,
+            created_at: message.created_at,
+            parent_id: message.parent_id,
+            reply_count: message.reply_count,
+            last_reply_at: message.last_reply_at,
+            reactions: Vec::new(),
+            seen_by: Vec::new(),
+        }
+    }
+}
+
+#[derive(GraphQLObject, Debug)]
+pub struct PageInfo {
+    pub has_next_page: bool,
+    pub has_previous_page: bool,
+    pub start_cursor: Option<String>,
+    pub end_cursor: Option<String>,
+}
+
+#[derive(GraphQLObject, Debug)]
+pub struct MessageEdge {
+    pub cursor: String,
+    pub node: Message,
+}
+
+/// Relay connection of messages, newest first.
+#[derive(GraphQLObject, Debug)]
+pub struct MessageConnection {
+    pub edges: Vec<MessageEdge>,
+    pub page_info: PageInfo,
+}
+
+impl MessageConnection {
+    // `messages` is one page fetched with `limit + 1` rows in `direction` order
+    pub fn new(
+        mut messages: Vec<SocketMessageContent>,
+        limit: usize,
+        direction: PageDirection,
+        has_cursor: bool,
+    ) -> Self {
+        let has_more = messages.len() > limit;
+        messages.truncate(limit);
+
+        let (has_next_page, has_previous_page) = match direction {
+            PageDirection::Older => (has_more, has_cursor),
+            PageDirection::Newer => {
+                messages.reverse();
+                (has_cursor, has_more)
+            }
+        };
+
+        let edges: Vec<MessageEdge> = messages
+            .into_iter()
+            .map(|message| MessageEdge {
+                cursor: MessageCursor {
+                    created_at: message.created_at,
+                    id: message.id,
+                }
+                .encode(),
+                node: message.into(),
+            })
+            .collect();
+
+        MessageConnection {
+            page_info: PageInfo {
+                has_next_page,
+                has_previous_page,
+                start_cursor: edges.first().map(|edge| edge.cursor.clone()),
+                end_cursor: edges.last().map(|edge| edge.cursor.clone()),
+            },
+            edges,
+        }
+    }
+
+    pub fn message_ids(&self) -> Vec<Uuid> {
+        self.edges.iter().map(|edge| edge.node.id).collect()
+    }
+
+    // `reactions` of every message of the page, in display order
+    pub fn with_reactions(mut self, reactions: Vec<ReactionCount>) -> Self {
+        for edge in self.edges.iter_mut() {
+            edge.node.reactions = reactions
+                .iter()
+                .filter(|reaction| reaction.message_id == edge.node.id)
+                .cloned()
+                .collect();
+        }
+
+        self
+    }
+
+    // `readers` of every message of the page, the author is never one of them
+    pub fn with_seen_by(mut self, readers: Vec<MessageReader>) -> Self {
+        for edge in self.edges.iter_mut() {
+            edge.node.seen_by = readers
+                .iter()
+                .filter(|reader| reader.message_id == edge.node.id)
+                .map(|reader| reader.user.clone())
+                .collect();
+        }
+
+        self
+    }
+}
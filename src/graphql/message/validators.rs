@@ -0,0 +1,40 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::errors::{AppError, AppErrorType};
+
+pub struct ReactionEmoji(String);
+
+impl AsRef<str> for ReactionEmoji {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl ReactionEmoji {
+    // a single grapheme, emoji with skin tones or joiners are several chars
+    pub fn parse(s: String) -> Result<ReactionEmoji, AppError> {
+        let s = s.trim().to_string();
+
+        let is_single_grapheme = s.graphemes(true).count() == 1;
+
+        let is_too_long = s.len() > 64;
+
+        // plain letters, digits and punctuation, keycap emoji carry a combining mark
+        let is_text = s.is_ascii()
+            || s.chars().all(char::is_alphanumeric)
+            || s.chars().any(|c| c.is_whitespace() || c.is_control());
+
+        if !is_single_grapheme || is_too_long || is_text {
+            Err(AppError::new(
+                format!("{} is not a valid reaction.", s),
+                AppErrorType::ValidationError(format!("{} is not a valid reaction.", s)),
+            ))
+        } else {
+            Ok(Self(s))
+        }
+    }
+
+    pub fn inner(self) -> String {
+        self.0
+    }
+}
@@ -1,4 +1,8 @@
+pub mod api_token;
+pub mod extract;
 pub mod handlers;
+pub mod message;
 pub mod root;
+pub mod session;
 pub mod user;
-pub mod room;
\ No newline at end of file
+pub mod room;
@@ -1 +1,2 @@
-pub mod schema;
\ No newline at end of file
+pub mod schema;
+mod validators;
@@ -1,25 +1,57 @@
 use juniper::{GraphQLInputObject, GraphQLObject};
 use serde::{Serialize, Deserialize};
+use sqlx::FromRow;
 use uuid::Uuid;
 use derivative::{self, Derivative};
 
+use crate::{
+    errors::AppError,
+    graphql::room::validators::{RoomDescription, RoomName},
+};
 
-
-#[derive(Serialize, Deserialize, GraphQLInputObject)]
+#[derive(Serialize, Deserialize, GraphQLInputObject, Debug)]
 pub struct RoomInput {
-    name: String,
-    description: String,
+    pub name: String,
+    pub description: String,
+}
+
+impl RoomInput {
+    pub fn validate_room_input(self) -> Result<Self, AppError> {
+        let name = RoomName::parse(self.name)?;
+        let description = RoomDescription::parse(self.description)?;
+
+        Ok(RoomInput {
+            name: name.inner(),
+            description: description.inner(),
+        })
+    }
 }
 
-#[derive(Serialize, Deserialize, GraphQLObject, Debug, Clone, Derivative)]
+pub static MESSAGE_PREVIEW_LENGTH: i32 = 100;
+
+/// `last_message_preview` - first characters of the newest message \
+/// `last_activity_at` - time of the newest message, or of the creation of an empty room \
+/// `unread_count` / `mention_count` - messages of others the requesting user has not read,
+/// and those of them mentioning the user with `@name`
+#[derive(Serialize, Deserialize, GraphQLObject, Debug, Clone, FromRow, Derivative)]
 #[derivative(Default)]
 pub struct Room {
     #[derivative(Default(value = "Uuid::new_v4()"))]
-    id: Uuid,
-    name: String,
-    description: String,
+    pub id: Uuid,
+    pub name: String,
+    pub description: String,
     #[derivative(Default(value = "chrono::Utc::now()"))]
     pub created_at: chrono::DateTime<chrono::Utc>,
     #[derivative(Default(value = "chrono::Utc::now()"))]
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    #[sqlx(default)]
+    pub last_message_id: Option<Uuid>,
+    #[sqlx(default)]
+    pub last_message_preview: Option<String>,
+    #[derivative(Default(value = "chrono::Utc::now()"))]
+    pub last_activity_at: chrono::DateTime<chrono::Utc>,
+    #[sqlx(default)]
+    pub unread_count: i32,
+    #[sqlx(default)]
+    pub mention_count: i32,
 }
@@ -0,0 +1,59 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::errors::{AppError, AppErrorType};
+
+pub struct RoomName(String);
+
+pub struct RoomDescription(String);
+
+impl AsRef<str> for RoomName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl RoomName {
+    pub fn parse(s: String) -> Result<RoomName, AppError> {
+        let s = s.trim().to_string();
+
+        let is_empty = s.is_empty();
+
+        let is_too_long = s.graphemes(true).count() > 255;
+
+        if is_empty || is_too_long {
+            Err(AppError::new(
+                format!("{} is not a valid room name.", s),
+                AppErrorType::ValidationError(format!("{} is not a valid room name.", s)),
+            ))
+        } else {
+            Ok(Self(s))
+        }
+    }
+
+    pub fn inner(self) -> String {
+        self.0
+    }
+}
+
+impl AsRef<str> for RoomDescription {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl RoomDescription {
+    pub fn parse(s: String) -> Result<RoomDescription, AppError> {
+        if s.graphemes(true).count() > 255 {
+            Err(AppError::new(
+                "Room description is too long.".to_string(),
+                AppErrorType::ValidationError("Room description is too long.".to_string()),
+            ))
+        } else {
+            Ok(Self(s))
+        }
+    }
+
+    pub fn inner(self) -> String {
+        self.0
+    }
+}
@@ -1,24 +1,230 @@
 use crate::{
-    crypt::token::Claims,
-    graphql::user::schema::{User, UserUpdate},
-    sql::user::{get_user, update_user},
+    configuration::get_configuration,
+    crypt::{
+        api_token::{generate_api_token, ApiTokenScope},
+        hash::hash_password,
+        keys::JwtKeys,
+        token::Claims,
+    },
+    errors::{AppError, AppErrorType},
+    graphql::{
+        api_token::schema::{ApiToken, ApiTokenInput, CreatedApiToken},
+        message::schema::{
+            Message, MessageConnection, MessageCursor, PageDirection, DEFAULT_PAGE_SIZE,
+            MAX_PAGE_SIZE,
+        },
+        session::schema::Session,
+        user::{
+            schema::{TotpEnrollment, User, UserUpdate},
+            validators::UserName,
+        },
+    },
+    service::{
+        account::send_verification_email,
+        mailer::Mailer,
+        session::end_sessions,
+        stream::{DeadLetter, DeadLetterStream},
+        throttle::LoginThrottle,
+        two_factor::{confirm_totp, enroll_totp, remove_totp},
+    },
+    sql::{
+        api_token::{get_api_token_owner, get_api_tokens, insert_api_token, revoke_api_token},
+        message::{get_message, get_messages},
+        reaction::get_reaction_counts,
+        read_cursor::get_message_readers,
+        room::{create_room, get_room, get_rooms, is_room_member, join_room, leave_room},
+        session::{get_sessions, revoke_other_sessions, revoke_session},
+        user::{get_user_by_id, insert_user, is_admin, update_user},
+    },
+    startup::AppState,
+    ws::{
+        rooms::ChatRooms,
+        schema::{
+            MessageAuthor, PresenceChange, SocketMessage, SocketMessageUpdate, SocketReaction,
+            SocketReadReceipt,
+        },
+    },
 };
-use juniper::{Context, EmptySubscription, FieldResult, IntoFieldError, RootNode};
+use futures_util::stream::{self, Stream};
+use juniper::{Context, FieldError, FieldResult, IntoFieldError, RootNode};
+use redis::aio::ConnectionManager;
 use sqlx::PgPool;
-use uuid::timestamp::context;
+use std::{pin::Pin, sync::Arc};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+use uuid::Uuid;
 
-use super::room::schema::Room;
+use super::room::schema::{Room, RoomInput};
 
 pub struct GraphQLContext {
     pub pool: PgPool,
+    pub redis: ConnectionManager,
+    pub chats: ChatRooms,
+    pub keys: JwtKeys,
+    pub mailer: Arc<dyn Mailer>,
     pub claims: Claims,
 }
 
 impl Context for GraphQLContext {}
 
 impl GraphQLContext {
-    pub fn new(pool: PgPool, claims: Claims) -> Self {
-        GraphQLContext { pool, claims }
+    pub fn new(state: &AppState, claims: Claims) -> Self {
+        GraphQLContext {
+            pool: state.pool.clone(),
+            redis: state.redis.clone(),
+            chats: state.chats.clone(),
+            keys: state.keys.clone(),
+            mailer: state.mailer.clone(),
+            claims,
+        }
+    }
+
+    pub async fn user(&self) -> Result<User, AppError> {
+        get_user_by_id(&self.pool, self.claims.user_id()?).await
+    }
+
+    // api tokens of admins are not admins
+    pub async fn require_admin(&self) -> Result<(), AppError> {
+        self.claims.require_login()?;
+        let user_id = self.claims.user_id()?;
+        if is_admin(&self.pool, user_id).await? {
+            Ok(())
+        } else {
+            Err(AppError::new(
+                format!("User {} is not an admin.", user_id),
+                AppErrorType::ForbiddenError("Admin only".to_string()),
+            ))
+        }
+    }
+
+    // 403 when the user is not a member of the room or the token is restricted to other rooms
+    pub async fn require_member(
+        &self,
+        room_id: Uuid,
+        scope: ApiTokenScope,
+    ) -> Result<Uuid, AppError> {
+        let user_id = self.claims.scoped_user_id(scope)?;
+        self.claims.require_room(room_id)?;
+        if !is_room_member(&self.pool, room_id, user_id).await? {
+            return Err(AppError::new(
+                format!("User {} is not a member of room {}.", user_id, room_id),
+                AppErrorType::ForbiddenError("Not a member of the room".to_string()),
+            ));
+        }
+
+        Ok(user_id)
+    }
+
+    // one page of thread roots of the room, or of replies with a `parent_id`
+    async fn message_page(
+        &self,
+        room_id: Uuid,
+        parent_id: Option<Uuid>,
+        first: Option<i32>,
+        after: Option<String>,
+        before: Option<String>,
+    ) -> Result<MessageConnection, AppError> {
+        let user_id = self.require_member(room_id, ApiTokenScope::MessagesRead).await?;
+
+        let (cursor, direction) = match (after, before) {
+            (Some(_), Some(_)) => {
+                return Err(AppError::new(
+                    "Only one of after and before can be set.".to_string(),
+                    AppErrorType::ValidationError(
+                        "Only one of after and before can be set.".to_string(),
+                    ),
+                ))
+            }
+            (None, Some(before)) => (Some(before), PageDirection::Newer),
+            (after, None) => (after, PageDirection::Older),
+        };
+        let cursor = cursor
+            .map(|cursor| MessageCursor::parse(&cursor))
+            .transpose()?;
+        let limit = first.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+        let has_cursor = cursor.is_some();
+
+        // one extra row tells whether there is another page
+        let messages = get_messages(
+            &self.pool,
+            room_id,
+            parent_id,
+            cursor,
+            direction,
+            limit as i64 + 1,
+        )
+        .await?;
+
+        let connection = MessageConnection::new(messages, limit, direction, has_cursor);
+        let message_ids = connection.message_ids();
+        let reactions = get_reaction_counts(&self.pool, &message_ids, user_id).await?;
+        let readers = get_message_readers(&self.pool, &message_ids).await?;
+
+        Ok(connection.with_reactions(reactions).with_seen_by(readers))
+    }
+
+    // events of the room broadcast channel picked by `select`, the stream ends when the user
+    // is removed from the room or the session is revoked
+    async fn room_events<T, F>(&self, room_id: Uuid, select: F) -> Result<RoomEventStream<T>, AppError>
+    where
+        T: Send + 'static,
+        F: Fn(SocketMessage) -> Option<T> + Send + 'static,
+    {
+        let user_id = self.require_member(room_id, ApiTokenScope::MessagesRead).await?;
+
+        let sid = self.claims.sid;
+        let subscription = self.chats.join(room_id);
+
+        Ok(Box::pin(stream::unfold(
+            (subscription, select),
+            move |(mut subscription, select)| async move {
+                loop {
+                    let event = match subscription.recv().await {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("Subscription of room {} skipped {} events", room_id, skipped);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => return None,
+                    };
+
+                    match serde_json::from_slice::<SocketMessage>(&event) {
+                        Ok(SocketMessage::MemberRemoved(id)) if id == user_id => return None,
+                        Ok(SocketMessage::SessionRevoked(sids))
+                            if sid.is_some_and(|sid| sids.contains(&sid)) =>
+                        {
+                            return None
+                        }
+                        Ok(event) => {
+                            if let Some(item) = select(event) {
+                                return Some((Ok(item), (subscription, select)));
+                            }
+                        }
+                        Err(e) => warn!("Room event deserialization failed: {}", e),
+                    }
+                }
+            },
+        )))
+    }
+
+    // api tokens are managed for the user itself or, by admins, for a bot
+    pub async fn api_token_owner(&self, bot_id: Option<Uuid>) -> Result<Uuid, AppError> {
+        self.claims.require_login()?;
+
+        let Some(bot_id) = bot_id else {
+            return self.claims.user_id();
+        };
+
+        self.require_admin().await?;
+        let bot = get_user_by_id(&self.pool, bot_id).await?;
+        if !bot.bot {
+            return Err(AppError::new(
+                format!("User {} is not a bot.", bot_id),
+                AppErrorType::ValidationError("Not a bot".to_string()),
+            ));
+        }
+
+        Ok(bot.id)
     }
 }
 
@@ -28,14 +234,110 @@ pub struct QueryRoot;
 impl QueryRoot {
     #[graphql(description = "Getting a single user based on id.")]
     async fn user(context: &GraphQLContext) -> FieldResult<User> {
-        get_user(&context.pool, &context.claims.email)
+        context.user().await.map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Getting rooms the user is a member of, most recently active first.")]
+    async fn rooms(context: &GraphQLContext) -> FieldResult<Vec<Room>> {
+        let user_id = context
+            .claims
+            .scoped_user_id(ApiTokenScope::RoomsRead)
+            .map_err(|e| e.into_field_error())?;
+
+        get_rooms(&context.pool, user_id, None)
             .await
             .map_err(|e| e.into_field_error())
     }
 
-    #[graphql(description = "Getting user rooms.")]
-    async fn rooms(context: &GraphQLContext) -> FieldResult<Vec<Room>> {
-        todo!()
+    #[graphql(description = "Getting a single room based on id.")]
+    async fn room(context: &GraphQLContext, id: Uuid) -> FieldResult<Room> {
+        // 404 for unknown rooms, 403 for rooms the user is not a member of
+        let room = get_room(&context.pool, id)
+            .await
+            .map_err(|e| e.into_field_error())?;
+        let user_id = context
+            .require_member(id, ApiTokenScope::RoomsRead)
+            .await
+            .map_err(|e| e.into_field_error())?;
+
+        get_rooms(&context.pool, user_id, Some(id))
+            .await
+            .map(|rooms| rooms.into_iter().next().unwrap_or(room))
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Getting room messages newest first, `after` pages to older \
+        messages and `before` to newer ones. Thread replies are left out.")]
+    async fn messages(
+        context: &GraphQLContext,
+        room_id: Uuid,
+        first: Option<i32>,
+        after: Option<String>,
+        before: Option<String>,
+    ) -> FieldResult<MessageConnection> {
+        context
+            .message_page(room_id, None, first, after, before)
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Getting the replies of a thread newest first, paged like messages.")]
+    async fn thread(
+        context: &GraphQLContext,
+        parent_id: Uuid,
+        first: Option<i32>,
+        after: Option<String>,
+        before: Option<String>,
+    ) -> FieldResult<MessageConnection> {
+        let parent = get_message(&context.pool, parent_id)
+            .await
+            .map_err(|e| e.into_field_error())?;
+
+        context
+            .message_page(parent.room, Some(parent.id), first, after, before)
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Admin only. Getting the oldest dead-lettered async events.")]
+    async fn dead_letters(context: &GraphQLContext, count: Option<i32>) -> FieldResult<Vec<DeadLetter>> {
+        context.require_admin().await.map_err(|e| e.into_field_error())?;
+
+        DeadLetterStream::new(context.redis.clone())
+            .list(count.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize)
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Getting the api tokens of the user, admins can pass a bot id \
+        to get the tokens of that bot.")]
+    async fn api_tokens(context: &GraphQLContext, bot_id: Option<Uuid>) -> FieldResult<Vec<ApiToken>> {
+        let owner = context
+            .api_token_owner(bot_id)
+            .await
+            .map_err(|e| e.into_field_error())?;
+
+        get_api_tokens(&context.pool, owner)
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Getting the active sessions of the user, most recently seen first.")]
+    async fn sessions(context: &GraphQLContext) -> FieldResult<Vec<Session>> {
+        context.claims.require_login().map_err(|e| e.into_field_error())?;
+        let user_id = context.claims.user_id().map_err(|e| e.into_field_error())?;
+
+        let sessions = get_sessions(&context.pool, user_id)
+            .await
+            .map_err(|e| e.into_field_error())?;
+
+        Ok(sessions
+            .into_iter()
+            .map(|session| Session {
+                current: context.claims.sid == Some(session.id),
+                ..session
+            })
+            .collect())
     }
 }
 
@@ -43,16 +345,426 @@ pub struct MutationRoot;
 
 #[juniper::graphql_object(Context = GraphQLContext, name = "Mutation")]
 impl MutationRoot {
-    #[graphql(description = "Updating user name based on id.")]
+    #[graphql(description = "Updating user name based on id, only admins update other users.")]
     pub async fn update_user(context: &GraphQLContext, user: UserUpdate) -> FieldResult<User> {
+        context.claims.require_login().map_err(|e| e.into_field_error())?;
+        let user_id = context.claims.user_id().map_err(|e| e.into_field_error())?;
+        if user.id != user_id {
+            context.require_admin().await.map_err(|e| e.into_field_error())?;
+        }
+
         update_user(&context.pool, user)
             .await
             .map_err(|e| e.into_field_error())
     }
+
+    #[graphql(description = "Creating a room, the creator joins it automatically.")]
+    pub async fn create_room(context: &GraphQLContext, input: RoomInput) -> FieldResult<Room> {
+        let user_id = context
+            .claims
+            .scoped_user_id(ApiTokenScope::RoomsWrite)
+            .map_err(|e| e.into_field_error())?;
+        let input = input.validate_room_input().map_err(|e| e.into_field_error())?;
+        let room = Room {
+            name: input.name,
+            description: input.description,
+            ..Default::default()
+        };
+
+        create_room(&context.pool, room, user_id)
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Joining a room based on id.")]
+    pub async fn join_room(context: &GraphQLContext, id: Uuid) -> FieldResult<Room> {
+        let user_id = context
+            .claims
+            .scoped_user_id(ApiTokenScope::RoomsWrite)
+            .and_then(|user_id| context.claims.require_room(id).map(|_| user_id))
+            .map_err(|e| e.into_field_error())?;
+        let room = get_room(&context.pool, id)
+            .await
+            .map_err(|e| e.into_field_error())?;
+
+        join_room(&context.pool, room.id, user_id)
+            .await
+            .map_err(|e| e.into_field_error())?;
+
+        Ok(room)
+    }
+
+    #[graphql(description = "Leaving a room based on id, returns whether the user was a member.")]
+    pub async fn leave_room(context: &GraphQLContext, id: Uuid) -> FieldResult<bool> {
+        let user_id = context
+            .claims
+            .scoped_user_id(ApiTokenScope::RoomsWrite)
+            .and_then(|user_id| context.claims.require_room(id).map(|_| user_id))
+            .map_err(|e| e.into_field_error())?;
+
+        let left = leave_room(&context.pool, id, user_id)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(|e| e.into_field_error())?;
+
+        // closes the live sockets of the user in this room
+        if left {
+            context
+                .chats
+                .broadcast(id, &SocketMessage::MemberRemoved(user_id));
+        }
+
+        Ok(left)
+    }
+
+    #[graphql(description = "Sending a new email verification link to the user.")]
+    pub async fn send_verification_email(context: &GraphQLContext) -> FieldResult<bool> {
+        context.claims.require_login().map_err(|e| e.into_field_error())?;
+        let user = context.user().await.map_err(|e| e.into_field_error())?;
+        let settings = get_configuration().map_err(|e| e.into_field_error())?;
+
+        send_verification_email(
+            &context.pool,
+            &context.keys,
+            context.mailer.as_ref(),
+            &settings.mail,
+            &user,
+        )
+        .await
+        .map_err(|e| e.into_field_error())?;
+
+        Ok(true)
+    }
+
+    #[graphql(description = "Starting two-factor enrolment, the otpauth uri is shown as a QR code \
+        by the client. Two-factor authentication is enabled by confirmTotp.")]
+    pub async fn enroll_totp(context: &GraphQLContext) -> FieldResult<TotpEnrollment> {
+        context.claims.require_login().map_err(|e| e.into_field_error())?;
+        let user = context.user().await.map_err(|e| e.into_field_error())?;
+        let settings = get_configuration().map_err(|e| e.into_field_error())?;
+
+        enroll_totp(&context.pool, &settings.two_factor, &user)
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Enabling two-factor authentication with a first code from the \
+        authenticator app, returns the recovery codes which are not shown again.")]
+    pub async fn confirm_totp(context: &GraphQLContext, code: String) -> FieldResult<Vec<String>> {
+        context.claims.require_login().map_err(|e| e.into_field_error())?;
+        let user = context.user().await.map_err(|e| e.into_field_error())?;
+        let settings = get_configuration().map_err(|e| e.into_field_error())?;
+
+        confirm_totp(&context.pool, &settings.two_factor, &user, &code)
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Disabling two-factor authentication with a current or recovery code.")]
+    pub async fn disable_totp(context: &GraphQLContext, code: String) -> FieldResult<bool> {
+        context.claims.require_login().map_err(|e| e.into_field_error())?;
+        let user = context.user().await.map_err(|e| e.into_field_error())?;
+        let settings = get_configuration().map_err(|e| e.into_field_error())?;
+
+        remove_totp(&context.pool, &settings.two_factor, &user, &code)
+            .await
+            .map_err(|e| e.into_field_error())?;
+
+        Ok(true)
+    }
+
+    #[graphql(description = "Admin only. Lifting the login lockout of an email, \
+        returns whether it was locked.")]
+    pub async fn unlock_account(context: &GraphQLContext, email: String) -> FieldResult<bool> {
+        context.require_admin().await.map_err(|e| e.into_field_error())?;
+        let settings = get_configuration().map_err(|e| e.into_field_error())?;
+
+        LoginThrottle::new(context.redis.clone(), &settings.login_throttle)
+            .unlock(&email)
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Admin only. Putting dead-lettered events back on their streams, \
+        returns the number of redriven events.")]
+    pub async fn redrive_dead_letters(context: &GraphQLContext, ids: Vec<String>) -> FieldResult<i32> {
+        context.require_admin().await.map_err(|e| e.into_field_error())?;
+
+        let mut stream = DeadLetterStream::new(context.redis.clone());
+        let mut redriven = 0;
+        for id in ids {
+            if stream.redrive(&id).await.map_err(|e| e.into_field_error())? {
+                redriven += 1;
+            }
+        }
+
+        Ok(redriven)
+    }
+
+    #[graphql(description = "Revoking a session of the user, its tokens stop working and its \
+        sockets are closed. Returns whether the session was active.")]
+    pub async fn revoke_session(context: &GraphQLContext, id: Uuid) -> FieldResult<bool> {
+        context.claims.require_login().map_err(|e| e.into_field_error())?;
+        let user_id = context.claims.user_id().map_err(|e| e.into_field_error())?;
+        let settings = get_configuration().map_err(|e| e.into_field_error())?;
+
+        if !revoke_session(&context.pool, user_id, id)
+            .await
+            .map_err(|e| e.into_field_error())?
+        {
+            return Ok(false);
+        }
+
+        end_sessions(
+            &context.pool,
+            &mut context.redis.clone(),
+            &context.chats,
+            user_id,
+            vec![id],
+            settings.token_max_age,
+        )
+        .await
+        .map_err(|e| e.into_field_error())?;
+
+        Ok(true)
+    }
+
+    #[graphql(description = "Revoking every session of the user but the current one, \
+        returns the number of revoked sessions.")]
+    pub async fn revoke_other_sessions(context: &GraphQLContext) -> FieldResult<i32> {
+        context.claims.require_login().map_err(|e| e.into_field_error())?;
+        let user_id = context.claims.user_id().map_err(|e| e.into_field_error())?;
+        let settings = get_configuration().map_err(|e| e.into_field_error())?;
+
+        let revoked = revoke_other_sessions(&context.pool, user_id, context.claims.sid)
+            .await
+            .map_err(|e| e.into_field_error())?;
+        let count = revoked.len() as i32;
+
+        end_sessions(
+            &context.pool,
+            &mut context.redis.clone(),
+            &context.chats,
+            user_id,
+            revoked,
+            settings.token_max_age,
+        )
+        .await
+        .map_err(|e| e.into_field_error())?;
+
+        Ok(count)
+    }
+
+    #[graphql(description = "Admin only. Creating a bot account, bots can not log in \
+        and act through api tokens only.")]
+    pub async fn create_bot(context: &GraphQLContext, name: String) -> FieldResult<User> {
+        context.claims.require_login().map_err(|e| e.into_field_error())?;
+        context.require_admin().await.map_err(|e| e.into_field_error())?;
+        let settings = get_configuration().map_err(|e| e.into_field_error())?;
+        let name = UserName::parse(name).map_err(|e| e.into_field_error())?;
+
+        // nobody knows the password and the address can not receive a reset link
+        let id = Uuid::new_v4();
+        let bot = User {
+            id,
+            name: name.inner(),
+            email: format!("{}@bots.invalid", id.simple()),
+            password: hash_password(Uuid::new_v4().to_string(), &settings.argon2)
+                .map_err(|e| e.into_field_error())?,
+            bot: true,
+            ..Default::default()
+        };
+
+        insert_user(&context.pool, bot)
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Creating an api token, the token is returned once and sent as \
+        `Authorization: Bearer fc_...` instead of a login token.")]
+    pub async fn create_api_token(
+        context: &GraphQLContext,
+        input: ApiTokenInput,
+    ) -> FieldResult<CreatedApiToken> {
+        let owner = context
+            .api_token_owner(input.bot_id)
+            .await
+            .map_err(|e| e.into_field_error())?;
+        let input = input
+            .validate_api_token_input()
+            .map_err(|e| e.into_field_error())?;
+
+        let (token, token_hash) = generate_api_token();
+        let api_token = insert_api_token(&context.pool, owner, input, &token_hash)
+            .await
+            .map_err(|e| e.into_field_error())?;
+
+        Ok(CreatedApiToken { token, api_token })
+    }
+
+    #[graphql(description = "Revoking an api token of the user, admins can revoke any token. \
+        Returns whether the token was active.")]
+    pub async fn revoke_api_token(context: &GraphQLContext, id: Uuid) -> FieldResult<bool> {
+        context.claims.require_login().map_err(|e| e.into_field_error())?;
+        let user_id = context.claims.user_id().map_err(|e| e.into_field_error())?;
+
+        let Some(owner) = get_api_token_owner(&context.pool, id)
+            .await
+            .map_err(|e| e.into_field_error())?
+        else {
+            return Ok(false);
+        };
+        if owner != user_id {
+            context.require_admin().await.map_err(|e| e.into_field_error())?;
+        }
+
+        revoke_api_token(&context.pool, id)
+            .await
+            .map(|revoked| revoked.is_some())
+            .map_err(|e| e.into_field_error())
+    }
+}
+
+type RoomEventStream<T> = Pin<Box<dyn Stream<Item = Result<T, FieldError>> + Send>>;
+
+pub struct SubscriptionRoot;
+
+#[juniper::graphql_subscription(context = GraphQLContext, name = "Subscription")]
+impl SubscriptionRoot {
+    #[graphql(description = "Messages sent to the room.")]
+    async fn message_added(
+        context: &GraphQLContext,
+        room_id: Uuid,
+    ) -> FieldResult<RoomEventStream<Message>> {
+        context
+            .room_events(room_id, |event| match event {
+                SocketMessage::Message(message) => Some(Message::from(message)),
+                _ => None,
+            })
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Thread replies sent to the room, to one thread with a parent id.")]
+    async fn thread_reply_added(
+        context: &GraphQLContext,
+        room_id: Uuid,
+        parent_id: Option<Uuid>,
+    ) -> FieldResult<RoomEventStream<Message>> {
+        context
+            .room_events(room_id, move |event| match event {
+                SocketMessage::ThreadReply(message)
+                    if parent_id.is_none() || message.parent_id == parent_id =>
+                {
+                    Some(Message::from(message))
+                }
+                _ => None,
+            })
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Edits of messages of the room.")]
+    async fn message_updated(
+        context: &GraphQLContext,
+        room_id: Uuid,
+    ) -> FieldResult<RoomEventStream<SocketMessageUpdate>> {
+        context
+            .room_events(room_id, |event| match event {
+                SocketMessage::Update(update) => Some(update),
+                _ => None,
+            })
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Ids of messages deleted from the room, one list per deletion.")]
+    async fn message_deleted(
+        context: &GraphQLContext,
+        room_id: Uuid,
+    ) -> FieldResult<RoomEventStream<Vec<Uuid>>> {
+        context
+            .room_events(room_id, |event| match event {
+                SocketMessage::Delete(ids) => Some(ids),
+                _ => None,
+            })
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Reactions added to messages of the room.")]
+    async fn reaction_added(
+        context: &GraphQLContext,
+        room_id: Uuid,
+    ) -> FieldResult<RoomEventStream<SocketReaction>> {
+        context
+            .room_events(room_id, |event| match event {
+                SocketMessage::ReactionAdded(reaction) => Some(reaction),
+                _ => None,
+            })
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Read cursors of the room members moving forward.")]
+    async fn read_receipt(
+        context: &GraphQLContext,
+        room_id: Uuid,
+    ) -> FieldResult<RoomEventStream<SocketReadReceipt>> {
+        context
+            .room_events(room_id, |event| match event {
+                SocketMessage::ReadReceipt(receipt) => Some(receipt),
+                _ => None,
+            })
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Reactions removed from messages of the room.")]
+    async fn reaction_removed(
+        context: &GraphQLContext,
+        room_id: Uuid,
+    ) -> FieldResult<RoomEventStream<SocketReaction>> {
+        context
+            .room_events(room_id, |event| match event {
+                SocketMessage::ReactionRemoved(reaction) => Some(reaction),
+                _ => None,
+            })
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Users typing in the room.")]
+    async fn typing(
+        context: &GraphQLContext,
+        room_id: Uuid,
+    ) -> FieldResult<RoomEventStream<MessageAuthor>> {
+        context
+            .room_events(room_id, |event| match event {
+                SocketMessage::UserTyping(author) => Some(author),
+                _ => None,
+            })
+            .await
+            .map_err(|e| e.into_field_error())
+    }
+
+    #[graphql(description = "Users coming online or going offline in the room.")]
+    async fn presence_changed(
+        context: &GraphQLContext,
+        room_id: Uuid,
+    ) -> FieldResult<RoomEventStream<PresenceChange>> {
+        context
+            .room_events(room_id, |event| match event {
+                SocketMessage::PresenceChanged(change) => Some(change),
+                _ => None,
+            })
+            .await
+            .map_err(|e| e.into_field_error())
+    }
 }
 
-pub type Schema = RootNode<'static, QueryRoot, MutationRoot, EmptySubscription<GraphQLContext>>;
+pub type Schema = RootNode<'static, QueryRoot, MutationRoot, SubscriptionRoot>;
 
 pub fn create_schema() -> Schema {
-    Schema::new(QueryRoot, MutationRoot, EmptySubscription::new())
+    Schema::new(QueryRoot, MutationRoot, SubscriptionRoot)
 }
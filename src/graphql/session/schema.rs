@@ -0,0 +1,19 @@
+use juniper::GraphQLObject;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Login of a user on one device, kept alive by refreshing its tokens. \
+/// `last_seen_at` - time of the login or of the last token refresh \
+/// `current` - whether the request was made from this session
+#[derive(Serialize, Deserialize, GraphQLObject, Debug, Clone, FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub device_label: Option<String>,
+    pub ip: String,
+    pub user_agent: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_seen_at: chrono::DateTime<chrono::Utc>,
+    #[sqlx(skip)]
+    pub current: bool,
+}
@@ -1,2 +1,2 @@
 pub mod schema;
-mod validators;
+pub mod validators;
@@ -1,7 +1,8 @@
 use crate::{
+    configuration::{Argon2Settings, PasswordPolicySettings},
     crypt::hash::hash_password,
     errors::AppError,
-    graphql::user::validators::{UserEmail, UserName},
+    graphql::user::validators::{UserEmail, UserName, UserPassword},
 };
 use derivative::{self, Derivative};
 use juniper::{GraphQLInputObject, GraphQLObject};
@@ -29,11 +30,21 @@ pub struct User {
     pub name: String,
     pub email: String,
     #[graphql(ignore)]
+    #[serde(skip_serializing)]
     pub password: String,
     #[derivative(Default(value = "chrono::Utc::now()"))]
     pub created_at: chrono::DateTime<chrono::Utc>,
     #[derivative(Default(value = "chrono::Utc::now()"))]
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub verified_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub totp_enabled_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub bot: bool,
+}
+
+#[derive(GraphQLObject, Debug)]
+pub struct TotpEnrollment {
+    pub otpauth_uri: String,
+    pub secret: String,
 }
 
 #[derive(Deserialize, Serialize, GraphQLInputObject, Debug)]
@@ -44,11 +55,16 @@ pub struct UserInput {
 }
 
 impl UserInput {
-    pub fn validate_user_input(self) -> Result<Self, AppError> {
+    pub fn validate_user_input(
+        self,
+        policy: &PasswordPolicySettings,
+        argon2: &Argon2Settings,
+    ) -> Result<Self, AppError> {
         let name = UserName::parse(self.name)?;
         let email = UserEmail::parse(self.email)?;
+        let password = UserPassword::parse(self.password, policy)?;
 
-        let hash = hash_password(self.password).expect("Failed to hash password.");
+        let hash = hash_password(password.inner(), argon2)?;
 
         Ok(UserInput {
             name: name.inner(),
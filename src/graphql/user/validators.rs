@@ -1,12 +1,19 @@
 use unicode_segmentation::UnicodeSegmentation;
 use validator::validate_email;
 
-use crate::errors::{AppError, AppErrorType};
+use crate::{
+    configuration::PasswordPolicySettings,
+    errors::{AppError, AppErrorType},
+};
+
+static COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
 
 pub struct UserName(String);
 
 pub struct UserEmail(String);
 
+pub struct UserPassword(String);
+
 impl AsRef<str> for UserEmail {
     fn as_ref(&self) -> &str {
         &self.0
@@ -58,3 +65,54 @@ impl UserName {
         self.0
     }
 }
+
+impl UserPassword {
+    // checks every rule of the policy and reports all failed ones at once
+    pub fn parse(s: String, policy: &PasswordPolicySettings) -> Result<UserPassword, AppError> {
+        let length = s.chars().count();
+        let mut errors = Vec::new();
+
+        if length < policy.min_length {
+            errors.push(format!(
+                "Password must be at least {} characters long.",
+                policy.min_length
+            ));
+        }
+        if length > policy.max_length {
+            errors.push(format!(
+                "Password must be at most {} characters long.",
+                policy.max_length
+            ));
+        }
+        if policy.require_lowercase && !s.chars().any(char::is_lowercase) {
+            errors.push("Password must contain a lowercase letter.".to_string());
+        }
+        if policy.require_uppercase && !s.chars().any(char::is_uppercase) {
+            errors.push("Password must contain an uppercase letter.".to_string());
+        }
+        if policy.require_digit && !s.chars().any(|c| c.is_ascii_digit()) {
+            errors.push("Password must contain a digit.".to_string());
+        }
+        if policy.require_symbol && !s.chars().any(|c| !c.is_alphanumeric() && !c.is_whitespace()) {
+            errors.push("Password must contain a symbol.".to_string());
+        }
+
+        let lowercase = s.to_lowercase();
+        if COMMON_PASSWORDS.lines().any(|common| common == lowercase) {
+            errors.push("Password is too common.".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(Self(s))
+        } else {
+            Err(AppError::new(
+                "Password does not meet the password policy.".to_string(),
+                AppErrorType::ValidationErrors(errors),
+            ))
+        }
+    }
+
+    pub fn inner(self) -> String {
+        self.0
+    }
+}
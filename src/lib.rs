@@ -1,3 +1,6 @@
+// `AppError` is returned by value throughout, boxing it would touch every call site
+#![allow(clippy::result_large_err)]
+
 pub mod configuration;
 pub mod crypt;
 pub mod db;
@@ -1,8 +1,12 @@
+#![allow(clippy::result_large_err)]
+
 use secrecy::ExposeSecret;
 
 use fast_chat::{
     configuration::get_configuration,
-    db::{init_db_connection, init_redis_connection},
+    crypt::keys::JwtKeys,
+    service::{mailer::mailer_from_settings, stream::ASYNC_EVENT_STREAMS},
+    db::{init_db_connection, init_redis_client, init_redis_connection},
     errors::{AppError, AppErrorType},
     startup::run,
     telemetry::init_subscriber,
@@ -35,10 +39,43 @@ async fn main() -> Result<(), AppError> {
     )
     .await?;
 
+    // redis client for the pubsub connection which fans out room broadcasts between instances,
+    // reopened whenever it drops
+    let redis_client = init_redis_client(
+        configuration
+            .redis
+            .redis_connection_string()
+            .expose_secret(),
+    )?;
+
+    // retried stream entries must not be claimed by other instances while they wait
+    let redis_worker_config = configuration
+        .redis
+        .redis_worker_config
+        .with_default_tasks(&ASYNC_EVENT_STREAMS);
+    redis_worker_config.validate()?;
+
+    // jwt keys init, parsed once for the lifetime of the server
+    let jwt_keys = JwtKeys::from_settings(&configuration.jwt)?;
+
+    // mailer init
+    let mailer = mailer_from_settings(&configuration.mail)?;
+
     // server init
     let listener = tokio::net::TcpListener::bind(address)
         .await
         .map_err(|e| AppError::new(e.to_string(), AppErrorType::InternalServerError))?;
 
-    Ok(run(listener, db_connection, redis_connection_manager, configuration.redis.redis_worker_config).await)
+    run(
+        listener,
+        db_connection,
+        redis_connection_manager,
+        redis_client,
+        redis_worker_config,
+        jwt_keys,
+        mailer,
+    )
+    .await;
+
+    Ok(())
 }
@@ -0,0 +1,147 @@
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::{
+    configuration::{Argon2Settings, MailSettings, PasswordPolicySettings},
+    crypt::{
+        hash::hash_password,
+        keys::JwtKeys,
+        user_token::{TokenPurpose, UserTokenClaims},
+    },
+    errors::{AppError, AppErrorType},
+    graphql::user::{schema::User, validators::UserPassword},
+    service::{
+        mailer::{Email, Mailer},
+        session::end_sessions,
+    },
+    sql::{
+        session::revoke_other_sessions,
+        user::{get_user, update_password, verify_email as set_email_verified},
+        user_token::{consume_user_token, insert_user_token},
+    },
+    startup::AppState,
+};
+
+pub async fn send_verification_email(
+    pool: &PgPool,
+    keys: &JwtKeys,
+    mailer: &dyn Mailer,
+    settings: &MailSettings,
+    user: &User,
+) -> Result<(), AppError> {
+    if user.verified_at.is_some() {
+        return Err(AppError::new(
+            format!("Email of user {} is already verified.", user.id),
+            AppErrorType::ValidationError("Email is already verified.".to_string()),
+        ));
+    }
+
+    let claims = UserTokenClaims::new(
+        user.id,
+        TokenPurpose::VerifyEmail,
+        settings.verification_token_max_age,
+    );
+    let token = claims.encode(keys)?;
+    insert_user_token(pool, &claims).await?;
+
+    mailer
+        .send(Email {
+            to: user.email.clone(),
+            subject: "Verify your email".to_string(),
+            body: format!(
+                "Hi {},\n\nconfirm your email by opening {}/verify-email?token={}\n",
+                user.name, settings.base_url, token
+            ),
+        })
+        .await
+}
+
+pub async fn verify_email(pool: &PgPool, keys: &JwtKeys, token: &str) -> Result<(), AppError> {
+    let claims = consume_token(pool, keys, token, TokenPurpose::VerifyEmail).await?;
+    set_email_verified(pool, claims.sub).await?;
+
+    Ok(())
+}
+
+// unknown emails are not reported, so accounts can not be enumerated
+pub async fn request_password_reset(
+    pool: &PgPool,
+    keys: &JwtKeys,
+    mailer: &dyn Mailer,
+    settings: &MailSettings,
+    email: &str,
+) -> Result<(), AppError> {
+    let user = match get_user(pool, email).await {
+        Ok(user) => user,
+        Err(e) => {
+            info!("Password reset requested for an unknown email: {}", e);
+            return Ok(());
+        }
+    };
+
+    let claims = UserTokenClaims::new(
+        user.id,
+        TokenPurpose::ResetPassword,
+        settings.password_reset_token_max_age,
+    );
+    let token = claims.encode(keys)?;
+    insert_user_token(pool, &claims).await?;
+
+    mailer
+        .send(Email {
+            to: user.email,
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Hi {},\n\nset a new password by opening {}/reset-password?token={}\n\n\
+                 If you did not ask for it, ignore this email.\n",
+                user.name, settings.base_url, token
+            ),
+        })
+        .await
+}
+
+// every login of the user ends with the reset, `token_max_age` is the access token time to
+// live in minutes
+pub async fn reset_password(
+    state: &AppState,
+    token: &str,
+    password: String,
+    policy: &PasswordPolicySettings,
+    argon2: &Argon2Settings,
+    token_max_age: i64,
+) -> Result<(), AppError> {
+    let pool = &state.pool;
+    // a rejected password does not use up the token
+    let password = UserPassword::parse(password, policy)?;
+    let claims = consume_token(pool, &state.keys, token, TokenPurpose::ResetPassword).await?;
+
+    update_password(pool, claims.sub, hash_password(password.inner(), argon2)?).await?;
+    let sids = revoke_other_sessions(pool, claims.sub, None).await?;
+    end_sessions(
+        pool,
+        &mut state.redis.clone(),
+        &state.chats,
+        claims.sub,
+        sids,
+        token_max_age,
+    )
+    .await
+}
+
+// checks the signature and purpose, then marks the token used
+pub async fn consume_token(
+    pool: &PgPool,
+    keys: &JwtKeys,
+    token: &str,
+    purpose: TokenPurpose,
+) -> Result<UserTokenClaims, AppError> {
+    let claims = UserTokenClaims::decode(keys, token, purpose)?;
+    if claims.purpose != purpose || !consume_user_token(pool, &claims).await? {
+        return Err(AppError::new(
+            format!("Token {} is invalid, expired or already used.", claims.jti),
+            AppErrorType::ValidationError("Token is invalid, expired or already used.".to_string()),
+        ));
+    }
+
+    Ok(claims)
+}
@@ -0,0 +1,146 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use axum::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+
+use crate::{
+    configuration::{MailSettings, MailTransportSettings},
+    errors::{AppError, AppErrorType},
+};
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), AppError>;
+}
+
+pub fn mailer_from_settings(settings: &MailSettings) -> Result<Arc<dyn Mailer>, AppError> {
+    Ok(match &settings.transport {
+        MailTransportSettings::Smtp {
+            host,
+            port,
+            username,
+            password,
+        } => Arc::new(SmtpMailer::new(
+            &settings.sender,
+            host,
+            *port,
+            Credentials::new(username.clone(), password.expose_secret().clone()),
+        )?),
+        MailTransportSettings::File { path } => Arc::new(FileMailer::new(path)),
+        MailTransportSettings::Memory => Arc::new(InMemoryMailer::default()),
+    })
+}
+
+pub struct SmtpMailer {
+    sender: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpMailer {
+    pub fn new(sender: &str, host: &str, port: u16, credentials: Credentials) -> Result<Self, AppError> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+            .map_err(mail_error)?
+            .port(port)
+            .credentials(credentials)
+            .build();
+
+        Ok(Self {
+            sender: sender.parse().map_err(mail_error)?,
+            transport,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<(), AppError> {
+        let message = lettre::Message::builder()
+            .from(self.sender.clone())
+            .to(email.to.parse().map_err(mail_error)?)
+            .subject(email.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(email.body)
+            .map_err(mail_error)?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(mail_error)
+    }
+}
+
+/// Writes every email to its own file, for local development.
+pub struct FileMailer {
+    path: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), AppError> {
+        tokio::fs::create_dir_all(&self.path)
+            .await
+            .map_err(mail_error)?;
+
+        let file = self.path.join(format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%d%H%M%S"),
+            Uuid::new_v4()
+        ));
+        let content = format!("To: {}\nSubject: {}\n\n{}\n", email.to, email.subject, email.body);
+
+        tokio::fs::write(file, content).await.map_err(mail_error)
+    }
+}
+
+/// Keeps sent emails in memory, for tests.
+#[derive(Clone, Default)]
+pub struct InMemoryMailer {
+    sent: Arc<Mutex<Vec<Email>>>,
+}
+
+impl InMemoryMailer {
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().expect("Failed to lock for mails.").clone()
+    }
+}
+
+#[async_trait]
+impl Mailer for InMemoryMailer {
+    async fn send(&self, email: Email) -> Result<(), AppError> {
+        self.sent
+            .lock()
+            .expect("Failed to lock for mails.")
+            .push(email);
+        Ok(())
+    }
+}
+
+fn mail_error(e: impl std::fmt::Display) -> AppError {
+    AppError::new(
+        "Sending email error.".to_string(),
+        AppErrorType::MailError(e.to_string()),
+    )
+}
@@ -0,0 +1,34 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{errors::AppError, sql::room::get_room_members, ws::schema::SocketMessageContent};
+
+// whether `content` mentions `name` with `@name`, ignoring case; the mention must not run
+// into the surrounding words, so `@al` does not mention `al` in `@alice` nor `bob@al`
+pub fn mentions(content: &str, name: &str) -> bool {
+    let content = content.to_lowercase();
+    let mention = format!("@{}", name.to_lowercase());
+
+    content.match_indices(&mention).any(|(start, _)| {
+        let before = content[..start].chars().next_back();
+        let after = content[start + mention.len()..].chars().next();
+        !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+    })
+}
+
+// members of the room other than the author mentioned in the message as it was sent
+pub async fn mentioned_members(
+    pool: &PgPool,
+    message: &SocketMessageContent,
+) -> Result<Vec<Uuid>, AppError> {
+    Ok(get_room_members(pool, message.room)
+        .await?
+        .into_iter()
+        .filter(|member| member.id != message.author.id && mentions(&message.content, &member.name))
+        .map(|member| member.id)
+        .collect())
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
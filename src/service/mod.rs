@@ -1,2 +1,10 @@
+pub mod account;
+pub mod mailer;
+pub mod mention;
+pub mod oidc;
+pub mod presence;
+pub mod session;
 pub mod stream;
+pub mod throttle;
+pub mod two_factor;
 pub mod worker;
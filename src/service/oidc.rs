@@ -0,0 +1,328 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use redis::{aio::ConnectionManager, AsyncCommands};
+use reqwest::Url;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    configuration::{Argon2Settings, OidcSettings},
+    crypt::hash::hash_password,
+    errors::{AppError, AppErrorType},
+    graphql::user::{schema::User, validators::UserName},
+    sql::{
+        identity::{get_identity_user, link_identity},
+        user::{find_user_by_email, get_user_by_id, insert_user, verify_email},
+    },
+};
+
+pub static OIDC_STATE_PREFIX: &str = "oidc_state:";
+// seconds the user has to finish signing in at the provider
+pub static OIDC_STATE_TTL: u64 = 600;
+
+static ID_TOKEN_ALGORITHMS: [Algorithm; 6] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::ES256,
+    Algorithm::ES384,
+];
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+/// Kept in Redis under the `state` of the authorization request until the callback.
+#[derive(Serialize, Deserialize)]
+struct PendingLogin {
+    verifier: String,
+    nonce: String,
+}
+
+#[derive(Deserialize)]
+struct TokenReply {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+    pub preferred_username: Option<String>,
+    pub nonce: Option<String>,
+}
+
+/// Authorization code flow with PKCE against the configured provider.
+pub struct OidcClient<'a> {
+    settings: &'a OidcSettings,
+    http: reqwest::Client,
+    redis: ConnectionManager,
+}
+
+impl<'a> OidcClient<'a> {
+    pub fn new(settings: &'a OidcSettings, redis: ConnectionManager) -> Self {
+        OidcClient {
+            settings,
+            http: reqwest::Client::new(),
+            redis,
+        }
+    }
+
+    // url the user is redirected to, the PKCE verifier and nonce wait in Redis for the callback
+    pub async fn authorization_url(&mut self) -> Result<String, AppError> {
+        let metadata = self.discover().await?;
+
+        let state = Uuid::new_v4().simple().to_string();
+        let pending = PendingLogin {
+            verifier: format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple()),
+            nonce: Uuid::new_v4().simple().to_string(),
+        };
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(pending.verifier.as_bytes()));
+
+        let _: () = self
+            .redis
+            .set_ex(
+                format!("{}{}", OIDC_STATE_PREFIX, state),
+                serde_json::to_vec(&pending).unwrap(),
+                OIDC_STATE_TTL,
+            )
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    "Saving oidc state error.".to_string(),
+                    AppErrorType::RedisError(e),
+                )
+            })?;
+
+        Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.settings.client_id),
+                ("redirect_uri", &self.settings.redirect_url),
+                ("scope", &self.settings.scopes.join(" ")),
+                ("state", &state),
+                ("nonce", &pending.nonce),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map(|url| url.to_string())
+        .map_err(provider_error)
+    }
+
+    // a state is accepted once, the returned claims are verified against the provider keys
+    pub async fn exchange(&mut self, code: &str, state: &str) -> Result<IdTokenClaims, AppError> {
+        let pending: Option<Vec<u8>> = self
+            .redis
+            .get_del(format!("{}{}", OIDC_STATE_PREFIX, state))
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    "Reading oidc state error.".to_string(),
+                    AppErrorType::RedisError(e),
+                )
+            })?;
+        let pending: PendingLogin = pending
+            .and_then(|pending| serde_json::from_slice(&pending).ok())
+            .ok_or_else(|| {
+                AppError::new(
+                    format!("Oidc state {} is unknown or expired.", state),
+                    AppErrorType::ValidationError("Sign-in expired, start it again.".to_string()),
+                )
+            })?;
+
+        let metadata = self.discover().await?;
+
+        let reply: TokenReply = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.settings.redirect_url),
+                ("client_id", &self.settings.client_id),
+                ("client_secret", self.settings.client_secret.expose_secret()),
+                ("code_verifier", &pending.verifier),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+
+        let claims = self.verify_id_token(&metadata, &reply.id_token).await?;
+        if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+            return Err(AppError::new(
+                "Id token nonce does not match.".to_string(),
+                AppErrorType::AuthorizationError("Invalid id token".to_string()),
+            ));
+        }
+
+        Ok(claims)
+    }
+
+    async fn discover(&self) -> Result<ProviderMetadata, AppError> {
+        let issuer = self.settings.issuer.trim_end_matches('/');
+        let metadata: ProviderMetadata = self
+            .http
+            .get(format!("{}/.well-known/openid-configuration", issuer))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(provider_error(format!(
+                "discovered issuer {} is not {}",
+                metadata.issuer, issuer
+            )));
+        }
+
+        Ok(metadata)
+    }
+
+    async fn verify_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+    ) -> Result<IdTokenClaims, AppError> {
+        let invalid = |message: String| {
+            AppError::new(
+                message,
+                AppErrorType::AuthorizationError("Invalid id token".to_string()),
+            )
+        };
+
+        let header = jsonwebtoken::decode_header(id_token)
+            .map_err(|e| invalid(format!("Id token header error: {}", e)))?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(invalid(format!("Id token algorithm {:?} is not accepted.", header.alg)));
+        }
+
+        let jwks: JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(provider_error)?
+            .json()
+            .await
+            .map_err(provider_error)?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None => jwks.keys.first(),
+        }
+        .ok_or_else(|| invalid("Id token key is unknown.".to_string()))?;
+        let key = DecodingKey::from_jwk(jwk)
+            .map_err(|e| invalid(format!("Id token key error: {}", e)))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.settings.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+
+        jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| invalid(format!("Id token validation error: {}", e)))
+    }
+}
+
+// finds the user linked to the identity, links an existing verified user by verified email
+// or provisions a new one
+pub async fn sign_in(
+    pool: &PgPool,
+    argon2: &Argon2Settings,
+    claims: IdTokenClaims,
+) -> Result<User, AppError> {
+    if let Some(user_id) = get_identity_user(pool, &claims.iss, &claims.sub).await? {
+        return get_user_by_id(pool, user_id).await;
+    }
+
+    let email = claims.email.clone().ok_or_else(|| {
+        AppError::new(
+            format!("Identity {} has no email.", claims.sub),
+            AppErrorType::ValidationError("The identity provider did not share an email.".to_string()),
+        )
+    })?;
+    let email_verified = claims.email_verified.unwrap_or(false);
+
+    let user = match find_user_by_email(pool, &email).await? {
+        // an unverified email could belong to anyone, it never takes over an account
+        Some(_) if !email_verified => {
+            return Err(AppError::new(
+                format!("Identity {} has an unverified email of an existing user.", claims.sub),
+                AppErrorType::ForbiddenError(
+                    "Email is not verified by the identity provider".to_string(),
+                ),
+            ))
+        }
+        // linking to an unverified account would hand it to whoever registered the email first,
+        // they could still sign in with their password; the owner verifies it first
+        Some(user) if user.verified_at.is_none() => {
+            return Err(AppError::new(
+                format!("Identity {} has the email of unverified user {}.", claims.sub, user.id),
+                AppErrorType::ForbiddenError(
+                    "Verify the email of your account before signing in with the identity provider"
+                        .to_string(),
+                ),
+            ))
+        }
+        Some(user) => user,
+        None => {
+            info!("Provisioning a user for identity {}", claims.sub);
+            let name = claims
+                .name
+                .clone()
+                .or(claims.preferred_username.clone())
+                .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string());
+            let name = UserName::parse(name)
+                .map(UserName::inner)
+                .unwrap_or_else(|_| "user".to_string());
+
+            // the account has no usable password until one is reset
+            insert_user(
+                pool,
+                User {
+                    name,
+                    email: email.clone(),
+                    password: hash_password(Uuid::new_v4().to_string(), argon2)?,
+                    ..Default::default()
+                },
+            )
+            .await?
+        }
+    };
+
+    // only a provisioned user can still be unverified here
+    if email_verified && user.verified_at.is_none() {
+        verify_email(pool, user.id).await?;
+    }
+    link_identity(pool, user.id, &claims.iss, &claims.sub, Some(&email)).await?;
+
+    get_user_by_id(pool, user.id).await
+}
+
+fn provider_error(e: impl std::fmt::Display) -> AppError {
+    AppError::new(
+        "Identity provider request error.".to_string(),
+        AppErrorType::IdentityProviderError(e.to_string()),
+    )
+}
@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use redis::{aio::ConnectionManager, AsyncCommands};
+use uuid::Uuid;
+
+use crate::errors::{AppError, AppErrorType};
+
+pub static PRESENCE_PREFIX: &str = "presence:";
+pub static PRESENCE_SOCKETS: &str = "presence:sockets";
+pub static PRESENCE_HEARTBEAT: Duration = Duration::from_secs(10);
+pub static PRESENCE_TTL: Duration = Duration::from_secs(30);
+
+/// Open sockets per user and room, shared by every instance. \
+/// A user is online in a room while at least one of their sockets is open there. \
+/// Sockets are sorted sets entries scored by the expiry of their last heartbeat,
+/// so the sockets of a crashed instance expire instead of keeping their users online.
+#[derive(Clone)]
+pub struct Presence {
+    redis: ConnectionManager,
+}
+
+impl Presence {
+    pub fn new(redis: ConnectionManager) -> Self {
+        Presence { redis }
+    }
+
+    // returns whether the user came online
+    pub async fn connect(
+        &mut self,
+        room: Uuid,
+        user: Uuid,
+        socket: Uuid,
+    ) -> Result<bool, AppError> {
+        let key = user_key(room, user);
+        let now = now_millis();
+        let (sockets,): (i64,) = redis::pipe()
+            .atomic()
+            .zrembyscore(&key, "-inf", now)
+            .ignore()
+            .zadd(&key, socket.to_string(), expiry(now))
+            .ignore()
+            .zcard(&key)
+            .pexpire(&key, PRESENCE_TTL.as_millis() as i64)
+            .ignore()
+            .zadd(PRESENCE_SOCKETS, socket_member(room, user, socket), expiry(now))
+            .ignore()
+            .query_async(&mut self.redis)
+            .await
+            .map_err(redis_error)?;
+
+        Ok(sockets == 1)
+    }
+
+    // keeps the socket alive for another `PRESENCE_TTL`
+    pub async fn heartbeat(
+        &mut self,
+        room: Uuid,
+        user: Uuid,
+        socket: Uuid,
+    ) -> Result<(), AppError> {
+        let key = user_key(room, user);
+        let now = now_millis();
+        let _: () = redis::pipe()
+            .atomic()
+            .zadd(&key, socket.to_string(), expiry(now))
+            .ignore()
+            .pexpire(&key, PRESENCE_TTL.as_millis() as i64)
+            .ignore()
+            .zadd(PRESENCE_SOCKETS, socket_member(room, user, socket), expiry(now))
+            .ignore()
+            .query_async(&mut self.redis)
+            .await
+            .map_err(redis_error)?;
+
+        Ok(())
+    }
+
+    // returns whether the user went offline
+    pub async fn disconnect(
+        &mut self,
+        room: Uuid,
+        user: Uuid,
+        socket: Uuid,
+    ) -> Result<bool, AppError> {
+        let removed: i64 = self
+            .redis
+            .zrem(PRESENCE_SOCKETS, socket_member(room, user, socket))
+            .await
+            .map_err(redis_error)?;
+        // already swept, the sweeper announced the user offline if it was the last socket
+        if removed == 0 {
+            return Ok(false);
+        }
+
+        self.remove(room, user, socket).await
+    }
+
+    // removes the expired sockets of every instance,
+    // returns the rooms and users which went offline with them
+    pub async fn sweep(&mut self) -> Result<Vec<(Uuid, Uuid)>, AppError> {
+        let expired: Vec<String> = self
+            .redis
+            .zrangebyscore(PRESENCE_SOCKETS, "-inf", now_millis())
+            .await
+            .map_err(redis_error)?;
+
+        let mut offline = vec![];
+        for member in expired {
+            // concurrent sweepers race for the removal, only the winner goes on
+            let removed: i64 = self
+                .redis
+                .zrem(PRESENCE_SOCKETS, &member)
+                .await
+                .map_err(redis_error)?;
+            if removed == 0 {
+                continue;
+            }
+
+            let Some((room, user, socket)) = parse_socket_member(&member) else {
+                continue;
+            };
+            if self.remove(room, user, socket).await? {
+                offline.push((room, user));
+            }
+        }
+
+        Ok(offline)
+    }
+
+    async fn remove(&mut self, room: Uuid, user: Uuid, socket: Uuid) -> Result<bool, AppError> {
+        let key = user_key(room, user);
+        let (sockets,): (i64,) = redis::pipe()
+            .atomic()
+            .zrem(&key, socket.to_string())
+            .ignore()
+            .zrembyscore(&key, "-inf", now_millis())
+            .ignore()
+            .zcard(&key)
+            .query_async(&mut self.redis)
+            .await
+            .map_err(redis_error)?;
+
+        Ok(sockets == 0)
+    }
+}
+
+fn user_key(room: Uuid, user: Uuid) -> String {
+    format!("{}{}:{}", PRESENCE_PREFIX, room, user)
+}
+
+fn socket_member(room: Uuid, user: Uuid, socket: Uuid) -> String {
+    format!("{}:{}:{}", room, user, socket)
+}
+
+fn parse_socket_member(member: &str) -> Option<(Uuid, Uuid, Uuid)> {
+    let mut ids = member.splitn(3, ':').map(Uuid::parse_str);
+    match (ids.next(), ids.next(), ids.next()) {
+        (Some(Ok(room)), Some(Ok(user)), Some(Ok(socket))) => Some((room, user, socket)),
+        _ => None,
+    }
+}
+
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+fn expiry(now: i64) -> i64 {
+    now + PRESENCE_TTL.as_millis() as i64
+}
+
+fn redis_error(e: redis::RedisError) -> AppError {
+    AppError::new(
+        "Presence error.".to_string(),
+        AppErrorType::RedisError(e),
+    )
+}
@@ -0,0 +1,169 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts, HeaderMap},
+    RequestPartsExt,
+};
+use redis::aio::ConnectionManager;
+use sqlx::PgPool;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    configuration::get_configuration,
+    crypt::token::revoke_session_tokens,
+    errors::{AppError, AppErrorType},
+    sql::room::get_room_ids,
+    ws::{rooms::ChatRooms, schema::SocketMessage},
+};
+
+pub static DEVICE_LABEL_HEADER: &str = "x-device-label";
+pub static MAX_DEVICE_LABEL_LENGTH: usize = 255;
+pub static FORWARDED_HEADER: &str = "forwarded";
+pub static X_FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// Where a login comes from, stored with its session and used by the login throttle. \
+/// `ip` - the client address, read from the forwarding headers behind a trusted proxy \
+/// `device_label` - optional name of the device given by the client in `X-Device-Label`
+#[derive(Debug, Clone)]
+pub struct SessionOrigin {
+    pub ip: IpAddr,
+    pub user_agent: Option<String>,
+    pub device_label: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SessionOrigin
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(address) = parts
+            .extract::<ConnectInfo<SocketAddr>>()
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    e.to_string(),
+                    AppErrorType::InternalServerError,
+                )
+            })?;
+        let trusted_proxies = get_configuration()?.trusted_proxies;
+
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+
+        Ok(SessionOrigin {
+            ip: client_ip(address.ip(), &parts.headers, &trusted_proxies),
+            user_agent: header(USER_AGENT.as_str()),
+            device_label: header(DEVICE_LABEL_HEADER)
+                .map(|label| label.chars().take(MAX_DEVICE_LABEL_LENGTH).collect()),
+        })
+    }
+}
+
+// hops are read from the right, each appended by the proxy in front of it, so the first one
+// not from a trusted proxy is the client; anything left of it could be forged by the client
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    let values = |name: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+    // `Forwarded: for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"`
+    let forwarded: Vec<&str> = values(FORWARDED_HEADER)
+        .into_iter()
+        .filter_map(|element| {
+            element.split(';').map(str::trim).find_map(|pair| {
+                pair.split_once('=')
+                    .filter(|(key, _)| key.eq_ignore_ascii_case("for"))
+                    .map(|(_, value)| value.trim_matches('"'))
+            })
+        })
+        .collect();
+    let hops = if forwarded.is_empty() {
+        values(X_FORWARDED_FOR_HEADER)
+    } else {
+        forwarded
+    };
+
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        match parse_hop(hop) {
+            Some(ip) => {
+                client = ip;
+                if !trusted_proxies.contains(&ip) {
+                    break;
+                }
+            }
+            // obfuscated or malformed hops end the chain, the last proxy is all that is known
+            None => break,
+        }
+    }
+
+    client
+}
+
+// `192.0.2.60`, `192.0.2.60:4711`, `2001:db8::1` or `[2001:db8::1]:4711`
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    if let Ok(ip) = hop.parse() {
+        return Some(ip);
+    }
+    if let Ok(address) = hop.parse::<SocketAddr>() {
+        return Some(address.ip());
+    }
+
+    hop.strip_prefix('[')
+        .and_then(|hop| hop.split_once(']'))
+        .and_then(|(ip, _)| ip.parse().ok())
+}
+
+// the access tokens of the sessions stop working at once and their sockets are closed,
+// `token_max_age` is the access token time to live in minutes
+pub async fn end_sessions(
+    pool: &PgPool,
+    redis: &mut ConnectionManager,
+    chats: &ChatRooms,
+    user_id: Uuid,
+    sids: Vec<Uuid>,
+    token_max_age: i64,
+) -> Result<(), AppError> {
+    if sids.is_empty() {
+        return Ok(());
+    }
+
+    for sid in &sids {
+        revoke_session_tokens(redis, *sid, token_max_age).await?;
+    }
+
+    // sockets only know their own session, every room of the user is told
+    match get_room_ids(pool, user_id).await {
+        Ok(rooms) => {
+            let message = SocketMessage::SessionRevoked(sids);
+            for room in rooms {
+                chats.broadcast(room, &message);
+            }
+        }
+        Err(e) => warn!("Closing sockets of revoked sessions failed: {}", e),
+    }
+
+    Ok(())
+}
@@ -1,34 +1,59 @@
-use std::pin::Pin;
-
-use futures_util::Future;
 use redis::{
     aio::ConnectionManager,
-    streams::{StreamId, StreamKey, StreamReadReply},
-    AsyncCommands, Value,
+    streams::{
+        StreamClaimReply, StreamId, StreamKey, StreamPendingCountReply, StreamPendingId,
+        StreamRangeReply, StreamReadOptions, StreamReadReply,
+    },
+    AsyncCommands, RedisResult, Value,
 };
+use juniper::GraphQLObject;
 use serde::{Deserialize, Serialize};
 use sqlx::{postgres::PgQueryResult, PgPool};
 use uuid::Uuid;
 
 use crate::{
     errors::{AppError, AppErrorType},
-    sql::message::{delete_messages, insert_message, mark_as_seen, update_message},
-    ws::schema::SocketMessageContent,
+    service::mention::mentioned_members,
+    sql::{
+        message::{delete_messages, insert_message, mark_as_seen, update_message},
+        reaction::{delete_reaction, insert_reaction},
+        read_cursor::advance_read_cursor,
+    },
+    ws::schema::{SocketMessageContent, SocketMessageUpdate, SocketReaction, SocketReadReceipt},
 };
 
 #[derive(Serialize, Deserialize, Clone)]
 pub enum AsyncEvent {
+    // messages are stored when they are sent, kept so entries still in the streams are applied
     Send(SocketMessageContent),
-    Update(SocketMessageContent),
+    Update(SocketMessageUpdate),
     Delete(Vec<Uuid>),
+    MarkAsRead(SocketReadReceipt),
+    // written before read cursors, kept so entries still in the streams are applied
     MarkAsSeen(Vec<Uuid>),
+    AddReaction(SocketReaction),
+    RemoveReaction(SocketReaction),
 }
 
 pub static REDIS_ENTRY_VALUE: &str = "value";
-pub static ASYNC_EVENT_SEND: &str = "ASYNC_EVENT_SEND";
-pub static ASYNC_EVENT_UPDATE: &str = "ASYNC_EVENT_UPDATE";
-pub static ASYNC_EVENT_DELETE: &str = "ASYNC_EVENT_DELETE";
+// edits and deletions share a stream so they are applied in the order they were made
+pub static ASYNC_EVENT_MESSAGE: &str = "ASYNC_EVENT_MESSAGE";
 pub static ASYNC_EVENT_MARK_AS_SEEN: &str = "ASYNC_EVENT_MARK_AS_SEEN";
+// additions and removals share a stream so they are read in the order they were made
+pub static ASYNC_EVENT_REACTION: &str = "ASYNC_EVENT_REACTION";
+pub static ASYNC_EVENT_DEAD_LETTER: &str = "ASYNC_EVENT_DEAD_LETTER";
+
+// streams read by the worker, dead letters are only read on demand
+pub static ASYNC_EVENT_STREAMS: [&str; 3] = [
+    "ASYNC_EVENT_MESSAGE",
+    "ASYNC_EVENT_MARK_AS_SEEN",
+    "ASYNC_EVENT_REACTION",
+];
+
+pub static DEAD_LETTER_STREAM: &str = "stream";
+pub static DEAD_LETTER_ENTRY_ID: &str = "entry_id";
+pub static DEAD_LETTER_ERROR: &str = "error";
+pub static DEAD_LETTER_ATTEMPTS: &str = "attempts";
 
 impl AsyncEvent {
     pub fn into_tuple_array(self) -> Vec<(&'static str, Vec<u8>)> {
@@ -36,7 +61,7 @@ impl AsyncEvent {
     }
 
     pub fn from_redis_value(value: &Value) -> Result<Self, AppError> {
-        if let Value::Data(data) = value {
+        if let Value::BulkString(data) = value {
             serde_json::from_slice(data).map_err(|_| {
                 AppError::new(
                     "Deserialization error.".to_string(),
@@ -52,6 +77,61 @@ impl AsyncEvent {
     }
 }
 
+/// Entry read from a stream, `id` is used to acknowledge it. \
+/// The payload is decoded lazily so a malformed entry can be dead-lettered as is.
+pub struct StreamEntry {
+    pub id: String,
+    pub payload: Vec<u8>,
+}
+
+impl StreamEntry {
+    pub fn from_stream_id(StreamId { id, map }: StreamId) -> Self {
+        let payload = match map.get(REDIS_ENTRY_VALUE) {
+            Some(Value::BulkString(data)) => data.clone(),
+            _ => Vec::new(),
+        };
+
+        StreamEntry { id, payload }
+    }
+
+    pub fn event(&self) -> Result<AsyncEvent, AppError> {
+        serde_json::from_slice(&self.payload).map_err(|_| {
+            AppError::new(
+                "Deserialization error.".to_string(),
+                AppErrorType::InternalServerError,
+            )
+        })
+    }
+}
+
+/// Event which failed `attempts` times \
+/// `id` - id in the dead-letter stream \
+/// `stream` / `entry_id` - where the event was originally read from \
+/// `payload` - original event payload \
+/// `error` - last error the event failed with
+#[derive(GraphQLObject, Debug, Clone)]
+pub struct DeadLetter {
+    pub id: String,
+    pub stream: String,
+    pub entry_id: String,
+    pub payload: String,
+    pub error: String,
+    pub attempts: i32,
+}
+
+impl DeadLetter {
+    fn from_stream_id(stream_id: &StreamId) -> Self {
+        DeadLetter {
+            id: stream_id.id.clone(),
+            stream: stream_id.get(DEAD_LETTER_STREAM).unwrap_or_default(),
+            entry_id: stream_id.get(DEAD_LETTER_ENTRY_ID).unwrap_or_default(),
+            payload: stream_id.get(REDIS_ENTRY_VALUE).unwrap_or_default(),
+            error: stream_id.get(DEAD_LETTER_ERROR).unwrap_or_default(),
+            attempts: stream_id.get(DEAD_LETTER_ATTEMPTS).unwrap_or_default(),
+        }
+    }
+}
+
 // https://redis.io/glossary/redis-queue/
 #[derive(Clone)]
 pub struct EventRedisStream {
@@ -70,7 +150,7 @@ impl EventRedisStream {
 
     pub async fn add_to_stream(&mut self, event: AsyncEvent) -> Result<(), AppError> {
         let args = event.into_tuple_array();
-        let result = self
+        let _: String = self
             .redis_connection_manager
             .xadd(&self.stream_key, "*", args.as_slice())
             .await
@@ -81,51 +161,256 @@ impl EventRedisStream {
                 )
             })?;
 
-        Ok(result)
+        Ok(())
     }
 
-    pub async fn read_stream(&mut self) -> Result<Vec<AsyncEvent>, AppError> {
+    // creates the consumer group (and the stream) if it does not exist yet
+    pub async fn create_group(&mut self, group: &str) -> Result<(), AppError> {
+        let result: RedisResult<()> = self
+            .redis_connection_manager
+            .xgroup_create_mkstream(&self.stream_key, group, "0")
+            .await;
+
+        match result {
+            Err(e) if e.code() != Some("BUSYGROUP") => Err(AppError::new(
+                format!("Group create with key - {} failed.", self.stream_key),
+                AppErrorType::RedisError(e),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // entries never delivered to any consumer of the group
+    pub async fn read_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        count: usize,
+    ) -> Result<Vec<StreamEntry>, AppError> {
+        let options = StreamReadOptions::default().group(group, consumer).count(count);
         let result: Option<StreamReadReply> = self
             .redis_connection_manager
-            .xread(&[&self.stream_key], &["0"])
+            .xread_options(&[&self.stream_key], &[">"], &options)
             .await
-            .unwrap();
-        let mut stream_events = Vec::<AsyncEvent>::new();
+            .map_err(|e| {
+                AppError::new(
+                    format!("Stream read with key - {} failed.", self.stream_key),
+                    AppErrorType::RedisError(e),
+                )
+            })?;
+
+        let mut stream_entries = Vec::<StreamEntry>::new();
         if let Some(stream_reply) = result {
             for StreamKey { ids, .. } in stream_reply.keys {
-                for StreamId { map, .. } in ids {
-                    if let Some(value) = map.get(REDIS_ENTRY_VALUE) {
-                        let event = AsyncEvent::from_redis_value(value)?;
-                        stream_events.push(event);
-                    } else {
-                        return Err(AppError::new(
-                            "StreamId read error".to_string(),
-                            AppErrorType::InternalServerError,
-                        ));
-                    }
+                for stream_id in ids {
+                    stream_entries.push(StreamEntry::from_stream_id(stream_id));
                 }
             }
-        } else {
-            return Err(AppError::new(
-                "Stream read error".to_string(),
-                AppErrorType::InternalServerError,
-            ));
         }
-        Ok(stream_events)
+        Ok(stream_entries)
+    }
+
+    // entries delivered but not acked yet which were idle for at least `min_idle_time` (ms),
+    // oldest first, along with their consumer and delivery count;
+    // only entries after `after` are listed, `None` starts with the oldest one
+    pub async fn pending(
+        &mut self,
+        group: &str,
+        min_idle_time: u64,
+        after: Option<&str>,
+        count: usize,
+    ) -> Result<Vec<StreamPendingId>, AppError> {
+        let start = after.map_or_else(|| "-".to_string(), |id| format!("({}", id));
+        let reply: StreamPendingCountReply = redis::cmd("XPENDING")
+            .arg(&self.stream_key)
+            .arg(group)
+            .arg("IDLE")
+            .arg(min_idle_time)
+            .arg(start)
+            .arg("+")
+            .arg(count)
+            .query_async(&mut self.redis_connection_manager)
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    format!("Stream pending with key - {} failed.", self.stream_key),
+                    AppErrorType::RedisError(e),
+                )
+            })?;
+
+        Ok(reply.ids)
+    }
+
+    // takes the entries over if they are still idle for `min_idle_time` (ms), so only one
+    // consumer gets them, deleted entries are left out
+    pub async fn claim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        min_idle_time: u64,
+        ids: &[String],
+    ) -> Result<Vec<StreamEntry>, AppError> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let claimed: StreamClaimReply = self
+            .redis_connection_manager
+            .xclaim(&self.stream_key, group, consumer, min_idle_time, ids)
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    format!("Stream claim with key - {} failed.", self.stream_key),
+                    AppErrorType::RedisError(e),
+                )
+            })?;
+
+        Ok(claimed
+            .ids
+            .into_iter()
+            .map(StreamEntry::from_stream_id)
+            .collect())
+    }
+
+    // acked entries are deleted right away, the stream only holds entries still to be
+    // persisted so it never has to be trimmed
+    pub async fn ack(&mut self, group: &str, ids: &[String]) -> Result<(), AppError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        redis::pipe()
+            .atomic()
+            .xack(&self.stream_key, group, ids)
+            .ignore()
+            .xdel(&self.stream_key, ids)
+            .ignore()
+            .query_async(&mut self.redis_connection_manager)
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    format!("Stream ack with key - {} failed.", self.stream_key),
+                    AppErrorType::RedisError(e),
+                )
+            })
     }
 
-    pub async fn process_event<'a>(
-        self,
-        db_pool: &'a PgPool,
+    // moves the entry into the dead-letter stream, the caller still has to ack it
+    pub async fn dead_letter(
+        &mut self,
+        entry: &StreamEntry,
+        error: &AppError,
+        attempts: u32,
+    ) -> Result<(), AppError> {
+        let args: [(&str, Vec<u8>); 5] = [
+            (REDIS_ENTRY_VALUE, entry.payload.clone()),
+            (DEAD_LETTER_STREAM, self.stream_key.clone().into_bytes()),
+            (DEAD_LETTER_ENTRY_ID, entry.id.clone().into_bytes()),
+            (DEAD_LETTER_ERROR, error.to_string().into_bytes()),
+            (DEAD_LETTER_ATTEMPTS, attempts.to_string().into_bytes()),
+        ];
+
+        self.redis_connection_manager
+            .xadd(ASYNC_EVENT_DEAD_LETTER, "*", &args)
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    format!("Dead-letter of entry {} failed.", entry.id),
+                    AppErrorType::RedisError(e),
+                )
+            })
+    }
+
+    pub async fn process_event(
+        &self,
+        db_pool: &PgPool,
         event: AsyncEvent,
-    ) -> Pin<Box<dyn Future<Output = Result<PgQueryResult, AppError>> + 'a>> {
+    ) -> Result<PgQueryResult, AppError> {
         match event {
-            AsyncEvent::MarkAsSeen(ids) => Box::pin(mark_as_seen(db_pool, ids)),
-            AsyncEvent::Delete(ids) => Box::pin(delete_messages(db_pool, ids)),
-            AsyncEvent::Send(message) => Box::pin(insert_message(db_pool, message)),
+            AsyncEvent::MarkAsRead(receipt) => advance_read_cursor(db_pool, receipt).await,
+            AsyncEvent::MarkAsSeen(ids) => mark_as_seen(db_pool, ids).await,
+            AsyncEvent::Delete(ids) => delete_messages(db_pool, ids).await,
+            AsyncEvent::Send(message) => {
+                let mentioned = mentioned_members(db_pool, &message).await?;
+                insert_message(db_pool, message, &mentioned).await
+            }
             AsyncEvent::Update(message) => {
-                Box::pin(update_message(db_pool, message.id, message.content))
+                update_message(db_pool, message.id, message.content).await
             }
+            AsyncEvent::AddReaction(reaction) => insert_reaction(db_pool, reaction).await,
+            AsyncEvent::RemoveReaction(reaction) => delete_reaction(db_pool, reaction).await,
+        }
+    }
+}
+
+/// Read side of the dead-letter stream, shared by all event streams.
+pub struct DeadLetterStream {
+    redis_connection_manager: ConnectionManager,
+}
+
+impl DeadLetterStream {
+    pub fn new(redis_connection_manager: ConnectionManager) -> Self {
+        Self {
+            redis_connection_manager,
         }
     }
+
+    pub async fn list(&mut self, count: usize) -> Result<Vec<DeadLetter>, AppError> {
+        let reply: StreamRangeReply = self
+            .redis_connection_manager
+            .xrange_count(ASYNC_EVENT_DEAD_LETTER, "-", "+", count)
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    "Dead-letter stream read failed.".to_string(),
+                    AppErrorType::RedisError(e),
+                )
+            })?;
+
+        Ok(reply.ids.iter().map(DeadLetter::from_stream_id).collect())
+    }
+
+    // puts the original payload back on its stream, returns whether the entry existed
+    pub async fn redrive(&mut self, id: &str) -> Result<bool, AppError> {
+        let reply: StreamRangeReply = self
+            .redis_connection_manager
+            .xrange(ASYNC_EVENT_DEAD_LETTER, id, id)
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    format!("Dead-letter entry {} read failed.", id),
+                    AppErrorType::RedisError(e),
+                )
+            })?;
+
+        let Some(stream_id) = reply.ids.first() else {
+            return Ok(false);
+        };
+        let stream: String = stream_id.get(DEAD_LETTER_STREAM).unwrap_or_default();
+        let payload: Vec<u8> = stream_id.get(REDIS_ENTRY_VALUE).unwrap_or_default();
+
+        let _: String = self
+            .redis_connection_manager
+            .xadd(&stream, "*", &[(REDIS_ENTRY_VALUE, payload)])
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    format!("Redrive of dead-letter entry {} failed.", id),
+                    AppErrorType::RedisError(e),
+                )
+            })?;
+
+        let _: i64 = self
+            .redis_connection_manager
+            .xdel(ASYNC_EVENT_DEAD_LETTER, &[id])
+            .await
+            .map_err(|e| {
+                AppError::new(
+                    format!("Delete of dead-letter entry {} failed.", id),
+                    AppErrorType::RedisError(e),
+                )
+            })?;
+
+        Ok(true)
+    }
 }
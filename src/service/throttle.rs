@@ -0,0 +1,143 @@
+use std::{net::IpAddr, time::Duration};
+
+use redis::{aio::ConnectionManager, AsyncCommands};
+
+use crate::{
+    configuration::LoginThrottleSettings,
+    errors::{AppError, AppErrorType},
+};
+
+pub static LOGIN_FAILURES_PREFIX: &str = "login_failures:";
+pub static LOGIN_LOCK_PREFIX: &str = "login_lock:";
+
+/// Failed login counters per email and per ip. \
+/// Failures are counted for `window` seconds, past `delay_after` failures every attempt is
+/// delayed progressively and past the max attempts the email or ip is locked for `lockout` seconds.
+pub struct LoginThrottle<'a> {
+    redis: ConnectionManager,
+    settings: &'a LoginThrottleSettings,
+}
+
+impl<'a> LoginThrottle<'a> {
+    pub fn new(redis: ConnectionManager, settings: &'a LoginThrottleSettings) -> Self {
+        LoginThrottle { redis, settings }
+    }
+
+    // fails with `AccountLocked` while the email or the ip is locked,
+    // otherwise waits for the delay earned by earlier failures
+    pub async fn check(&mut self, email: &str, ip: IpAddr) -> Result<(), AppError> {
+        for subject in [email_subject(email), ip_subject(ip)] {
+            let ttl: i64 = self
+                .redis
+                .ttl(format!("{}{}", LOGIN_LOCK_PREFIX, subject))
+                .await
+                .map_err(redis_error)?;
+            if ttl > 0 {
+                return Err(AppError::new(
+                    format!("Login of {} is locked.", subject),
+                    AppErrorType::AccountLocked(ttl as u64),
+                ));
+            }
+        }
+
+        let failures: Option<u64> = self
+            .redis
+            .get(format!("{}{}", LOGIN_FAILURES_PREFIX, email_subject(email)))
+            .await
+            .map_err(redis_error)?;
+        let delay = self.delay(failures.unwrap_or_default());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn record_failure(&mut self, email: &str, ip: IpAddr) -> Result<(), AppError> {
+        let subjects = [
+            (email_subject(email), self.settings.max_attempts_per_email),
+            (ip_subject(ip), self.settings.max_attempts_per_ip),
+        ];
+
+        for (subject, max_attempts) in subjects {
+            let key = format!("{}{}", LOGIN_FAILURES_PREFIX, subject);
+            let failures: u64 = self.redis.incr(&key, 1).await.map_err(redis_error)?;
+            if failures == 1 {
+                let _: () = self
+                    .redis
+                    .expire(&key, self.settings.window as i64)
+                    .await
+                    .map_err(redis_error)?;
+            }
+
+            if failures >= max_attempts {
+                let _: () = self
+                    .redis
+                    .set_ex(
+                        format!("{}{}", LOGIN_LOCK_PREFIX, subject),
+                        1,
+                        self.settings.lockout,
+                    )
+                    .await
+                    .map_err(redis_error)?;
+                let _: () = self.redis.del(&key).await.map_err(redis_error)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // the ip counter is kept, one valid account does not clear an ip guessing others
+    pub async fn record_success(&mut self, email: &str) -> Result<(), AppError> {
+        self.redis
+            .del(format!("{}{}", LOGIN_FAILURES_PREFIX, email_subject(email)))
+            .await
+            .map_err(redis_error)
+    }
+
+    // returns whether the email was locked
+    pub async fn unlock(&mut self, email: &str) -> Result<bool, AppError> {
+        let subject = email_subject(email);
+        let unlocked: u64 = self
+            .redis
+            .del(format!("{}{}", LOGIN_LOCK_PREFIX, subject))
+            .await
+            .map_err(redis_error)?;
+        let _: () = self
+            .redis
+            .del(format!("{}{}", LOGIN_FAILURES_PREFIX, subject))
+            .await
+            .map_err(redis_error)?;
+
+        Ok(unlocked > 0)
+    }
+
+    fn delay(&self, failures: u64) -> Duration {
+        if failures < self.settings.delay_after {
+            return Duration::ZERO;
+        }
+
+        let shift = (failures - self.settings.delay_after).min(16) as u32;
+        Duration::from_millis(
+            self.settings
+                .base_delay
+                .saturating_mul(1 << shift)
+                .min(self.settings.max_delay),
+        )
+    }
+}
+
+fn email_subject(email: &str) -> String {
+    format!("email:{}", email.trim().to_lowercase())
+}
+
+fn ip_subject(ip: IpAddr) -> String {
+    format!("ip:{}", ip)
+}
+
+fn redis_error(e: redis::RedisError) -> AppError {
+    AppError::new(
+        "Login throttle error.".to_string(),
+        AppErrorType::RedisError(e),
+    )
+}
@@ -0,0 +1,167 @@
+use std::net::IpAddr;
+
+use sqlx::PgPool;
+
+use crate::{
+    configuration::TwoFactorSettings,
+    crypt::{
+        keys::JwtKeys,
+        totp::{generate_recovery_codes, generate_totp_secret, hash_recovery_code, matching_step, totp},
+        user_token::{TokenPurpose, UserTokenClaims},
+    },
+    errors::{AppError, AppErrorType},
+    graphql::user::schema::{TotpEnrollment, User},
+    service::{account::consume_token, throttle::LoginThrottle},
+    sql::{
+        totp::{disable_totp, enable_totp, get_totp, set_totp_secret, use_recovery_code, use_totp_step},
+        user::get_user_by_id,
+        user_token::insert_user_token,
+    },
+};
+
+// stores a pending secret, totp is only required once a code for it is confirmed
+pub async fn enroll_totp(
+    pool: &PgPool,
+    settings: &TwoFactorSettings,
+    user: &User,
+) -> Result<TotpEnrollment, AppError> {
+    let secret = generate_totp_secret();
+    let otpauth_uri = totp(&secret, &settings.issuer, &user.email)?.get_url();
+
+    if !set_totp_secret(pool, user.id, &secret).await? {
+        return Err(AppError::new(
+            format!("Totp of user {} is already enabled.", user.id),
+            AppErrorType::ValidationError("Two-factor authentication is already enabled.".to_string()),
+        ));
+    }
+
+    Ok(TotpEnrollment {
+        otpauth_uri,
+        secret,
+    })
+}
+
+// returns the recovery codes, they are only ever shown here
+pub async fn confirm_totp(
+    pool: &PgPool,
+    settings: &TwoFactorSettings,
+    user: &User,
+    code: &str,
+) -> Result<Vec<String>, AppError> {
+    let state = get_totp(pool, user.id).await?;
+    let secret = match (state.secret, state.enabled_at) {
+        (Some(secret), None) => secret,
+        _ => {
+            return Err(AppError::new(
+                format!("Totp of user {} is not pending confirmation.", user.id),
+                AppErrorType::ValidationError("No two-factor enrolment to confirm.".to_string()),
+            ))
+        }
+    };
+
+    verify_totp_code(pool, settings, user, &secret, code).await?;
+
+    let codes = generate_recovery_codes();
+    let hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    enable_totp(pool, user.id, &hashes).await?;
+
+    Ok(codes)
+}
+
+pub async fn remove_totp(
+    pool: &PgPool,
+    settings: &TwoFactorSettings,
+    user: &User,
+    code: &str,
+) -> Result<(), AppError> {
+    verify_second_factor(pool, settings, user, code).await?;
+    disable_totp(pool, user.id).await
+}
+
+// accepts a current totp code or an unused recovery code
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    settings: &TwoFactorSettings,
+    user: &User,
+    code: &str,
+) -> Result<(), AppError> {
+    let state = get_totp(pool, user.id).await?;
+    let secret = match (state.secret, state.enabled_at) {
+        (Some(secret), Some(_)) => secret,
+        _ => {
+            return Err(AppError::new(
+                format!("Totp of user {} is not enabled.", user.id),
+                AppErrorType::ValidationError("Two-factor authentication is not enabled.".to_string()),
+            ))
+        }
+    };
+
+    if code.contains('-') {
+        if use_recovery_code(pool, user.id, &hash_recovery_code(code)).await? {
+            Ok(())
+        } else {
+            Err(invalid_code(user))
+        }
+    } else {
+        verify_totp_code(pool, settings, user, &secret, code).await
+    }
+}
+
+// the first login step, the token is exchanged for the session by `complete_login_challenge`
+pub async fn issue_login_challenge(
+    pool: &PgPool,
+    keys: &JwtKeys,
+    settings: &TwoFactorSettings,
+    user: &User,
+) -> Result<String, AppError> {
+    let claims = UserTokenClaims::new(user.id, TokenPurpose::TwoFactorLogin, settings.challenge_max_age);
+    let token = claims.encode(keys)?;
+    insert_user_token(pool, &claims).await?;
+
+    Ok(token)
+}
+
+// the challenge is used up by a wrong code too, the login starts over with the password;
+// wrong codes count as failed logins of the email and the ip, which are only cleared here
+pub async fn complete_login_challenge(
+    pool: &PgPool,
+    keys: &JwtKeys,
+    settings: &TwoFactorSettings,
+    throttle: &mut LoginThrottle<'_>,
+    ip: IpAddr,
+    challenge: &str,
+    code: &str,
+) -> Result<User, AppError> {
+    let claims = consume_token(pool, keys, challenge, TokenPurpose::TwoFactorLogin).await?;
+    let user = get_user_by_id(pool, claims.sub).await?;
+
+    throttle.check(&user.email, ip).await?;
+    if let Err(e) = verify_second_factor(pool, settings, &user, code).await {
+        throttle.record_failure(&user.email, ip).await?;
+        return Err(e);
+    }
+    throttle.record_success(&user.email).await?;
+
+    Ok(user)
+}
+
+async fn verify_totp_code(
+    pool: &PgPool,
+    settings: &TwoFactorSettings,
+    user: &User,
+    secret: &str,
+    code: &str,
+) -> Result<(), AppError> {
+    let totp = totp(secret, &settings.issuer, &user.email)?;
+    match matching_step(&totp, code.trim()) {
+        Some(step) if use_totp_step(pool, user.id, step).await? => Ok(()),
+        _ => Err(invalid_code(user)),
+    }
+}
+
+fn invalid_code(user: &User) -> AppError {
+    AppError::new(
+        format!("Invalid two-factor code of user {}.", user.id),
+        AppErrorType::AuthorizationError("Invalid two-factor code".to_string()),
+    )
+}
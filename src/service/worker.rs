@@ -3,9 +3,11 @@ use std::time::Duration;
 use redis::aio::ConnectionManager;
 use sqlx::PgPool;
 use tokio::time;
+use tracing::{error, warn};
 use crate::configuration::{RedisEventConfig, RedisWorkerConfig};
+use crate::errors::AppError;
 
-use super::stream::EventRedisStream;
+use super::stream::{EventRedisStream, StreamEntry};
 
 
 
@@ -25,22 +27,137 @@ impl RedisWorker {
     }
 
     pub fn spawn_worker(self) {
-        for RedisEventConfig { key, interval } in self.config.task_config {
-            let mut stream = EventRedisStream::new(&key, self.redis_connection_manager.clone());
+        let RedisWorkerConfig { group, consumer, claim_idle_time, task_config } = self.config;
+        for task in task_config {
+            let mut stream =
+                EventRedisStream::new(&task.key, self.redis_connection_manager.clone());
             let pg_pool = self.db_pool.clone();
-            let mut interval = time::interval(Duration::from_secs(interval));
+            let group = group.clone();
+            let consumer = consumer.clone();
+            let mut interval = time::interval(Duration::from_secs(task.interval));
             tokio::spawn(async move {
+                if let Err(e) = stream.create_group(&group).await {
+                    error!("Failed to create group for stream {}: {}", task.key, e);
+                }
                 loop {
                     interval.tick().await;
-                    if let Ok(events) = stream.read_stream().await {
-                        for event in events {
-                            let stream_clone = stream.clone();
-                            let _pinned_boxed_future = stream_clone.process_event(&pg_pool, event).await;
+                    // failed entries go first, nothing sleeps in here: they stay pending until
+                    // their backoff passed or, for entries of crashed consumers, `claim_idle_time`
+                    let due =
+                        retry_due(&mut stream, &group, &consumer, claim_idle_time * 1000, &task)
+                            .await;
+                    match due {
+                        Ok(entries) => {
+                            process_entries(&mut stream, &pg_pool, &group, &task, entries).await
+                        }
+                        Err(e) => warn!("Failed to claim stream {}: {}", task.key, e),
+                    }
+
+                    match stream.read_group(&group, &consumer, task.batch_size).await {
+                        Ok(entries) => {
+                            let entries = entries.into_iter().map(|entry| (entry, 1)).collect();
+                            process_entries(&mut stream, &pg_pool, &group, &task, entries).await
                         }
+                        Err(e) => warn!("Failed to read stream {}: {}", task.key, e),
                     }
                 }
             });
         }
-        ()
+    }
+}
+
+// claims pending entries due for another attempt, with the number of that attempt taken from
+// their delivery count
+async fn retry_due(
+    stream: &mut EventRedisStream,
+    group: &str,
+    consumer: &str,
+    claim_idle_time: u64,
+    task: &RedisEventConfig,
+) -> Result<Vec<(StreamEntry, u32)>, AppError> {
+    // entries still waiting for their backoff or of live consumers are skipped, so the pending
+    // list is paged through until a batch of due entries is found
+    let mut pending = Vec::new();
+    let mut after: Option<String> = None;
+    while pending.len() < task.batch_size {
+        let page = stream
+            .pending(group, task.backoff, after.as_deref(), task.batch_size)
+            .await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = Some(last.id.clone());
+        let exhausted = page.len() < task.batch_size;
+
+        pending.extend(page.into_iter().filter(|entry| {
+            let idle = entry.last_delivered_ms as u64;
+            if entry.consumer == consumer {
+                idle >= task.retry_delay(entry.times_delivered as u32)
+            } else {
+                idle >= claim_idle_time
+            }
+        }));
+        if exhausted {
+            break;
+        }
+    }
+    pending.truncate(task.batch_size);
+
+    let (own, others): (Vec<_>, Vec<_>) = pending
+        .iter()
+        .partition(|entry| entry.consumer == consumer);
+    let own: Vec<String> = own.into_iter().map(|entry| entry.id.clone()).collect();
+    let others: Vec<String> = others.into_iter().map(|entry| entry.id.clone()).collect();
+
+    let mut claimed = stream.claim(group, consumer, task.backoff, &own).await?;
+    claimed.extend(stream.claim(group, consumer, claim_idle_time, &others).await?);
+
+    Ok(claimed
+        .into_iter()
+        .map(|entry| {
+            let delivered = pending
+                .iter()
+                .find(|pending| pending.id == entry.id)
+                .map_or(0, |pending| pending.times_delivered as u32);
+            (entry, delivered + 1)
+        })
+        .collect())
+}
+
+// acknowledges persisted and dead-lettered entries, failed ones stay pending for a retry
+async fn process_entries(
+    stream: &mut EventRedisStream,
+    pg_pool: &PgPool,
+    group: &str,
+    task: &RedisEventConfig,
+    entries: Vec<(StreamEntry, u32)>,
+) {
+    let mut processed = Vec::with_capacity(entries.len());
+    for (entry, attempt) in entries {
+        let failure = match entry.event() {
+            // malformed entries are not retried
+            Err(e) => e,
+            Ok(event) => match stream.process_event(pg_pool, event).await {
+                Ok(_) => {
+                    processed.push(entry.id);
+                    continue;
+                }
+                Err(e) if attempt >= task.max_attempts => e,
+                Err(e) => {
+                    warn!("Stream entry {} attempt {} failed: {}", entry.id, attempt, e);
+                    continue;
+                }
+            },
+        };
+
+        warn!("Stream entry {} failed after {} attempts: {}", entry.id, attempt, failure);
+        match stream.dead_letter(&entry, &failure, attempt).await {
+            Ok(()) => processed.push(entry.id),
+            Err(e) => error!("Failed to dead-letter stream entry {}: {}", entry.id, e),
+        }
+    }
+
+    if let Err(e) = stream.ack(group, &processed).await {
+        warn!("Failed to acknowledge stream entries: {}", e);
     }
 }
@@ -0,0 +1,135 @@
+use sqlx::{FromRow, PgPool};
+use tracing::{instrument, Level};
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, AppErrorType},
+    graphql::api_token::schema::{ApiToken, ApiTokenInput},
+};
+
+/// Api token presented in a request along with the email of its user.
+#[derive(Debug, FromRow)]
+pub struct AuthenticatedApiToken {
+    #[sqlx(flatten)]
+    pub token: ApiToken,
+    pub email: String,
+}
+
+#[instrument(name = "Inserting an api token.", skip(pool, input, token_hash), level = Level::INFO)]
+pub async fn insert_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    input: ApiTokenInput,
+    token_hash: &str,
+) -> Result<ApiToken, AppError> {
+    let expires_at = input
+        .expires_in_days
+        .map(|days| chrono::Utc::now() + chrono::Duration::days(days as i64));
+
+    sqlx::query_as(
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, room_ids, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, user_id, name, scopes, room_ids, expires_at, last_used_at, revoked_at, created_at
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(input.name)
+    .bind(token_hash)
+    .bind(input.scopes)
+    .bind(input.room_ids)
+    .bind(expires_at)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Insert api token error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+#[instrument(name = "Getting api tokens.", skip(pool), level = Level::INFO)]
+pub async fn get_api_tokens(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiToken>, AppError> {
+    sqlx::query_as(
+        r#"
+        SELECT id, user_id, name, scopes, room_ids, expires_at, last_used_at, revoked_at, created_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Get api tokens error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// returns the user of the token when it was revoked by this call
+#[instrument(name = "Revoking an api token.", skip(pool), level = Level::INFO)]
+pub async fn revoke_api_token(pool: &PgPool, id: Uuid) -> Result<Option<Uuid>, AppError> {
+    sqlx::query_scalar(
+        "UPDATE api_tokens SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL RETURNING user_id",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Revoke api token error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+#[instrument(name = "Getting an api token owner.", skip(pool), level = Level::INFO)]
+pub async fn get_api_token_owner(pool: &PgPool, id: Uuid) -> Result<Option<Uuid>, AppError> {
+    sqlx::query_scalar("SELECT user_id FROM api_tokens WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| {
+            AppError::new(
+                "Get api token owner error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })
+}
+
+// a usable token is the one not revoked and not expired, its last use is recorded
+#[instrument(name = "Authenticating an api token.", skip(pool, token_hash), level = Level::INFO)]
+pub async fn authenticate_api_token(
+    pool: &PgPool,
+    token_hash: &str,
+) -> Result<Option<AuthenticatedApiToken>, AppError> {
+    sqlx::query_as(
+        r#"
+        WITH used AS (
+            UPDATE api_tokens
+            SET last_used_at = NOW()
+            WHERE token_hash = $1
+                AND revoked_at IS NULL
+                AND (expires_at IS NULL OR expires_at > NOW())
+            RETURNING id, user_id, name, scopes, room_ids, expires_at, last_used_at, revoked_at, created_at
+        )
+        SELECT used.*, users.email
+        FROM used
+        JOIN users ON users.id = used.user_id
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Authenticate api token error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
@@ -0,0 +1,56 @@
+use sqlx::PgPool;
+use tracing::{instrument, Level};
+use uuid::Uuid;
+
+use crate::errors::{AppError, AppErrorType};
+
+#[instrument(name = "Getting an identity user.", skip(pool), level = Level::INFO)]
+pub async fn get_identity_user(
+    pool: &PgPool,
+    issuer: &str,
+    subject: &str,
+) -> Result<Option<Uuid>, AppError> {
+    sqlx::query_scalar("SELECT user_id FROM user_identities WHERE issuer = $1 AND subject = $2")
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| {
+            AppError::new(
+                "Get identity user error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })
+}
+
+// linking the same identity twice is a no-op
+#[instrument(name = "Linking an identity.", skip(pool), level = Level::INFO)]
+pub async fn link_identity(
+    pool: &PgPool,
+    user_id: Uuid,
+    issuer: &str,
+    subject: &str,
+    email: Option<&str>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO user_identities (id, user_id, issuer, subject, email)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (issuer, subject) DO NOTHING
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(issuer)
+    .bind(subject)
+    .bind(email)
+    .execute(pool)
+    .await
+    .map(|_| ())
+    .map_err(|e| {
+        AppError::new(
+            "Link identity error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
@@ -4,18 +4,50 @@ use uuid::Uuid;
 
 use crate::{
     errors::{AppError, AppErrorType},
+    graphql::message::schema::{MessageCursor, PageDirection},
     ws::schema::{MessageStatus, SocketMessageContent},
 };
 
+// a reply counts towards its thread root only once and a message towards the unread and
+// mention counts of the members who have not read past it, redelivered events are no-ops
 #[instrument(name = "Send message", skip(pool), level = Level::INFO)]
 pub async fn insert_message(
     pool: &PgPool,
     message: SocketMessageContent,
+    mentioned: &[Uuid],
 ) -> Result<PgQueryResult, AppError> {
     sqlx::query(
         r#"
-        INSERT INTO messages (id, content, author, room, status, created_at)
-        VALUES ($1, $2, $3, $4, $5, $6)
+        WITH inserted AS (
+            INSERT INTO messages (id, content, author, room, status, created_at, parent_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (id) DO NOTHING
+            RETURNING id, author, room, parent_id, created_at
+        ),
+        mentions AS (
+            INSERT INTO message_mentions (message_id, user_id)
+            SELECT inserted.id, mentioned.user_id
+            FROM inserted, UNNEST($8::uuid[]) AS mentioned(user_id)
+        ),
+        counts AS (
+            UPDATE room_users ru
+            SET unread_count = ru.unread_count + 1,
+                mention_count = ru.mention_count + (ru.user_id = ANY($8))::int
+            FROM inserted
+            WHERE ru.room_id = inserted.room AND ru.user_id <> inserted.author
+                AND ru.joined_at < inserted.created_at
+                AND NOT EXISTS (
+                    SELECT 1 FROM room_read_cursors c
+                    WHERE c.room_id = ru.room_id AND c.user_id = ru.user_id
+                        AND (c.last_read_at, c.last_read_message_id)
+                            >= (inserted.created_at, inserted.id)
+                )
+        )
+        UPDATE messages
+        SET reply_count = reply_count + 1,
+            last_reply_at = GREATEST(last_reply_at, inserted.created_at)
+        FROM inserted
+        WHERE messages.id = inserted.parent_id
         "#,
     )
     .bind(message.id)
@@ -24,6 +56,8 @@ pub async fn insert_message(
     .bind(message.room)
     .bind(message.status)
     .bind(message.created_at)
+    .bind(message.parent_id)
+    .bind(mentioned)
     .execute(pool)
     .await
     .map_err(|e| {
@@ -34,6 +68,7 @@ pub async fn insert_message(
     })
 }
 
+// only applies `MarkAsSeen` entries queued before read cursors replaced the seen status
 #[instrument(name = "Marking messages as seen.", skip(pool), level = Level::INFO)]
 pub async fn mark_as_seen(pool: &PgPool, ids: Vec<Uuid>) -> Result<PgQueryResult, AppError> {
     sqlx::query(
@@ -56,49 +91,131 @@ pub async fn mark_as_seen(pool: &PgPool, ids: Vec<Uuid>) -> Result<PgQueryResult
 }
 
 #[instrument(name = "Getting a message", skip(pool), level = Level::INFO)]
-pub async fn get_message(pool: &PgPool, id: Uuid) -> Result<PgQueryResult, AppError> {
-    sqlx::query(
+pub async fn get_message(pool: &PgPool, id: Uuid) -> Result<SocketMessageContent, AppError> {
+    sqlx::query_as(
         r#"
-        SELECT * FROM messages m INNER JOIN users u ON m.author = u.id WHERE m.id = $1 LIMIT 1
+        SELECT m.id, m.content, m.room, m.status, m.created_at,
+            m.parent_id, m.reply_count, m.last_reply_at,
+            u.id AS author_id, u.name AS author_name
+        FROM messages m
+        INNER JOIN users u ON m.author = u.id
+        WHERE m.id = $1
         "#,
     )
     .bind(id)
-    .execute(pool)
+    .fetch_one(pool)
     .await
-    .map_err(|e| {
-        AppError::new(
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => AppError::new(
+            format!("Message {} does not exist.", id),
+            AppErrorType::MessageNotFound,
+        ),
+        e => AppError::new(
             "Get a message error.".to_string(),
             AppErrorType::DatabaseError(e),
-        )
+        ),
     })
 }
 
+// one page of room messages next to `cursor`, ordered away from it,
+// thread roots without a `parent` and the replies of the thread with one
 #[instrument(name = "Getting messages", skip(pool), level = Level::INFO)]
-pub async fn get_messages(pool: &PgPool, id: Uuid) -> Result<PgQueryResult, AppError> {
-    sqlx::query(
-        r#"
-        SELECT * FROM messages
-        WHERE room = $1
-        ORDER BY created_at DESC
-        "#,
-    )
-    .bind(id)
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        AppError::new(
-            "Get messageßs error.".to_string(),
-            AppErrorType::DatabaseError(e),
-        )
-    })
+pub async fn get_messages(
+    pool: &PgPool,
+    room: Uuid,
+    parent: Option<Uuid>,
+    cursor: Option<MessageCursor>,
+    direction: PageDirection,
+    limit: i64,
+) -> Result<Vec<SocketMessageContent>, AppError> {
+    let query = match direction {
+        PageDirection::Older => {
+            r#"
+            SELECT m.id, m.content, m.room, m.status, m.created_at,
+                m.parent_id, m.reply_count, m.last_reply_at,
+                u.id AS author_id, u.name AS author_name
+            FROM messages m
+            INNER JOIN users u ON m.author = u.id
+            WHERE m.room = $1
+                AND m.parent_id IS NOT DISTINCT FROM $5::uuid
+                AND ($2::timestamptz IS NULL OR (m.created_at, m.id) < ($2, $3::uuid))
+            ORDER BY m.created_at DESC, m.id DESC
+            LIMIT $4
+            "#
+        }
+        PageDirection::Newer => {
+            r#"
+            SELECT m.id, m.content, m.room, m.status, m.created_at,
+                m.parent_id, m.reply_count, m.last_reply_at,
+                u.id AS author_id, u.name AS author_name
+            FROM messages m
+            INNER JOIN users u ON m.author = u.id
+            WHERE m.room = $1
+                AND m.parent_id IS NOT DISTINCT FROM $5::uuid
+                AND ($2::timestamptz IS NULL OR (m.created_at, m.id) > ($2, $3::uuid))
+            ORDER BY m.created_at ASC, m.id ASC
+            LIMIT $4
+            "#
+        }
+    };
+
+    sqlx::query_as(query)
+        .bind(room)
+        .bind(cursor.as_ref().map(|cursor| cursor.created_at))
+        .bind(cursor.as_ref().map(|cursor| cursor.id))
+        .bind(limit)
+        .bind(parent)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            AppError::new(
+                "Get messages error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })
 }
 
+// replies of deleted thread roots go with them, thread roots of deleted replies are recounted;
+// the replies are deleted here rather than by the cascade so they leave the unread counts too
 #[instrument(name = "Deleting messages", skip(pool), level = Level::INFO)]
 pub async fn delete_messages(pool: &PgPool, ids: Vec<Uuid>) -> Result<PgQueryResult, AppError> {
     sqlx::query(
         r#"
-        DELETE FROM messages
-        WHERE id = ANY($1) 
+        WITH deleted AS (
+            DELETE FROM messages
+            WHERE id = ANY($1) OR parent_id = ANY($1)
+            RETURNING id, author, room, parent_id, created_at
+        ),
+        uncounted AS (
+            SELECT ru.room_id, ru.user_id,
+                COUNT(*)::int AS unread, COUNT(mm.user_id)::int AS mentions
+            FROM deleted
+            INNER JOIN room_users ru ON ru.room_id = deleted.room AND ru.user_id <> deleted.author
+                AND ru.joined_at < deleted.created_at
+            LEFT JOIN room_read_cursors c ON c.room_id = ru.room_id AND c.user_id = ru.user_id
+            LEFT JOIN message_mentions mm ON mm.message_id = deleted.id AND mm.user_id = ru.user_id
+            WHERE c.user_id IS NULL
+                OR (deleted.created_at, deleted.id) > (c.last_read_at, c.last_read_message_id)
+            GROUP BY ru.room_id, ru.user_id
+        ),
+        counts AS (
+            UPDATE room_users ru
+            SET unread_count = GREATEST(ru.unread_count - uncounted.unread, 0),
+                mention_count = GREATEST(ru.mention_count - uncounted.mentions, 0)
+            FROM uncounted
+            WHERE ru.room_id = uncounted.room_id AND ru.user_id = uncounted.user_id
+        )
+        UPDATE messages
+        SET reply_count = (
+                SELECT COUNT(*) FROM messages r
+                WHERE r.parent_id = messages.id AND r.id NOT IN (SELECT id FROM deleted)
+            ),
+            last_reply_at = (
+                SELECT MAX(r.created_at) FROM messages r
+                WHERE r.parent_id = messages.id AND r.id NOT IN (SELECT id FROM deleted)
+            )
+        WHERE messages.id IN (SELECT parent_id FROM deleted WHERE parent_id IS NOT NULL)
+            AND messages.id NOT IN (SELECT id FROM deleted)
         "#,
     )
     .bind(ids)
@@ -136,3 +253,55 @@ pub async fn update_message(
         )
     })
 }
+
+// (id, author) of the given messages which belong to the room
+#[instrument(name = "Getting message authors", skip(pool), level = Level::INFO)]
+pub async fn get_message_authors(
+    pool: &PgPool,
+    ids: &[Uuid],
+    room: Uuid,
+) -> Result<Vec<(Uuid, Uuid)>, AppError> {
+    sqlx::query_as(
+        r#"
+        SELECT id, author FROM messages
+        WHERE id = ANY($1) AND room = $2
+        "#,
+    )
+    .bind(ids)
+    .bind(room)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Get message authors error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// (id, created_at) of the newest of the given messages which belong to the room
+#[instrument(name = "Getting the latest message", skip(pool), level = Level::INFO)]
+pub async fn get_latest_message(
+    pool: &PgPool,
+    ids: &[Uuid],
+    room: Uuid,
+) -> Result<Option<(Uuid, chrono::DateTime<chrono::Utc>)>, AppError> {
+    sqlx::query_as(
+        r#"
+        SELECT id, created_at FROM messages
+        WHERE id = ANY($1) AND room = $2
+        ORDER BY created_at DESC, id DESC
+        LIMIT 1
+        "#,
+    )
+    .bind(ids)
+    .bind(room)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Get latest message error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
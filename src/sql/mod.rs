@@ -1,3 +1,11 @@
 pub mod user;
 pub mod message;
-pub mod room;
\ No newline at end of file
+pub mod room;
+pub mod identity;
+pub mod refresh_token;
+pub mod totp;
+pub mod user_token;
+pub mod api_token;
+pub mod session;
+pub mod reaction;
+pub mod read_cursor;
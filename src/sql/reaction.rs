@@ -0,0 +1,99 @@
+use sqlx::{postgres::PgQueryResult, PgPool};
+use tracing::{instrument, Level};
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, AppErrorType},
+    graphql::message::schema::ReactionCount,
+    ws::schema::SocketReaction,
+};
+
+// reacting twice with the same emoji is a no-op, a removal made after the reaction wins
+// even when its event was applied first
+#[instrument(name = "Adding a reaction", skip(pool), level = Level::INFO)]
+pub async fn insert_reaction(
+    pool: &PgPool,
+    reaction: SocketReaction,
+) -> Result<PgQueryResult, AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO message_reactions (message_id, user_id, emoji, created_at)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (message_id, user_id, emoji) DO UPDATE
+        SET created_at = EXCLUDED.created_at, removed_at = NULL
+        WHERE message_reactions.removed_at < EXCLUDED.created_at
+        "#,
+    )
+    .bind(reaction.id)
+    .bind(reaction.user.id)
+    .bind(reaction.emoji)
+    .bind(reaction.created_at)
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Insert reaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// removed reactions are kept with `removed_at` so a reaction made before the removal,
+// whose event is applied after it, is not restored
+#[instrument(name = "Removing a reaction", skip(pool), level = Level::INFO)]
+pub async fn delete_reaction(
+    pool: &PgPool,
+    reaction: SocketReaction,
+) -> Result<PgQueryResult, AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO message_reactions (message_id, user_id, emoji, created_at, removed_at)
+        VALUES ($1, $2, $3, $4, $4)
+        ON CONFLICT (message_id, user_id, emoji) DO UPDATE
+        SET removed_at = EXCLUDED.removed_at
+        WHERE message_reactions.created_at < EXCLUDED.removed_at
+        AND (message_reactions.removed_at IS NULL
+            OR message_reactions.removed_at < EXCLUDED.removed_at)
+        "#,
+    )
+    .bind(reaction.id)
+    .bind(reaction.user.id)
+    .bind(reaction.emoji)
+    .bind(reaction.created_at)
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Delete reaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// reaction counts per message and emoji, emoji ordered by their first use
+#[instrument(name = "Getting reactions", skip(pool), level = Level::INFO)]
+pub async fn get_reaction_counts(
+    pool: &PgPool,
+    message_ids: &[Uuid],
+    user_id: Uuid,
+) -> Result<Vec<ReactionCount>, AppError> {
+    sqlx::query_as(
+        r#"
+        SELECT message_id, emoji, COUNT(*)::int AS count, BOOL_OR(user_id = $2) AS reacted_by_me
+        FROM message_reactions
+        WHERE message_id = ANY($1) AND removed_at IS NULL
+        GROUP BY message_id, emoji
+        ORDER BY MIN(created_at), emoji
+        "#,
+    )
+    .bind(message_ids)
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Get reactions error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
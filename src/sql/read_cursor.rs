@@ -0,0 +1,120 @@
+use sqlx::{postgres::PgQueryResult, PgPool};
+use tracing::{instrument, Level};
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, AppErrorType},
+    graphql::message::schema::MessageReader,
+    ws::schema::SocketReadReceipt,
+};
+
+// cursors only move forward, receipts arriving out of order are no-ops; an advanced cursor
+// recounts the unread messages and mentions left after it
+#[instrument(name = "Advancing a read cursor.", skip(pool), level = Level::INFO)]
+pub async fn advance_read_cursor(
+    pool: &PgPool,
+    receipt: SocketReadReceipt,
+) -> Result<PgQueryResult, AppError> {
+    sqlx::query(
+        r#"
+        WITH advanced AS (
+            INSERT INTO room_read_cursors (user_id, room_id, last_read_message_id, last_read_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id, room_id) DO UPDATE
+            SET last_read_message_id = EXCLUDED.last_read_message_id,
+                last_read_at = EXCLUDED.last_read_at,
+                updated_at = EXCLUDED.updated_at
+            WHERE (room_read_cursors.last_read_at, room_read_cursors.last_read_message_id)
+                < (EXCLUDED.last_read_at, EXCLUDED.last_read_message_id)
+            RETURNING user_id, room_id, last_read_message_id, last_read_at
+        ),
+        unread AS (
+            SELECT m.id FROM advanced
+            INNER JOIN room_users ru ON ru.room_id = advanced.room_id AND ru.user_id = advanced.user_id
+            INNER JOIN messages m ON m.room = advanced.room_id
+            WHERE m.author <> advanced.user_id AND m.created_at > ru.joined_at
+                AND (m.created_at, m.id) > (advanced.last_read_at, advanced.last_read_message_id)
+        )
+        UPDATE room_users
+        SET unread_count = (SELECT COUNT(*) FROM unread),
+            mention_count = (
+                SELECT COUNT(*) FROM unread
+                INNER JOIN message_mentions mm ON mm.message_id = unread.id
+                WHERE mm.user_id = advanced.user_id
+            )
+        FROM advanced
+        WHERE room_users.room_id = advanced.room_id AND room_users.user_id = advanced.user_id
+        "#,
+    )
+    .bind(receipt.user.id)
+    .bind(receipt.room)
+    .bind(receipt.message_id)
+    .bind(receipt.message_created_at)
+    .bind(receipt.read_at)
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Advance read cursor error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// whether the receipt is past the stored cursor of its user, receipts which are not
+// would not advance it
+#[instrument(name = "Checking a read cursor.", skip(pool), level = Level::INFO)]
+pub async fn advances_read_cursor(
+    pool: &PgPool,
+    receipt: &SocketReadReceipt,
+) -> Result<bool, AppError> {
+    sqlx::query_scalar(
+        r#"
+        SELECT NOT EXISTS (
+            SELECT 1 FROM room_read_cursors
+            WHERE user_id = $1 AND room_id = $2
+            AND (last_read_at, last_read_message_id) >= ($3, $4)
+        )
+        "#,
+    )
+    .bind(receipt.user.id)
+    .bind(receipt.room)
+    .bind(receipt.message_created_at)
+    .bind(receipt.message_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Check read cursor error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// users other than the author whose read cursor is at or past each message
+#[instrument(name = "Getting message readers.", skip(pool), level = Level::INFO)]
+pub async fn get_message_readers(
+    pool: &PgPool,
+    message_ids: &[Uuid],
+) -> Result<Vec<MessageReader>, AppError> {
+    sqlx::query_as(
+        r#"
+        SELECT m.id AS message_id, u.id AS author_id, u.name AS author_name
+        FROM messages m
+        INNER JOIN room_read_cursors c ON c.room_id = m.room
+            AND (c.last_read_at, c.last_read_message_id) >= (m.created_at, m.id)
+        INNER JOIN users u ON u.id = c.user_id
+        WHERE m.id = ANY($1) AND c.user_id <> m.author
+        ORDER BY c.updated_at
+        "#,
+    )
+    .bind(message_ids)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Get message readers error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
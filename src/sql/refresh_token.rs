@@ -0,0 +1,193 @@
+use sqlx::PgPool;
+use tracing::{instrument, warn, Level};
+use uuid::Uuid;
+
+use crate::{
+    crypt::refresh::RefreshToken,
+    errors::{AppError, AppErrorType},
+};
+
+#[instrument(name = "Inserting a refresh token.", skip(pool, token), fields(token.user_id = %token.user_id), level = Level::INFO)]
+pub async fn insert_refresh_token(pool: &PgPool, token: &RefreshToken) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (id, family, user_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(token.id)
+    .bind(token.family)
+    .bind(token.user_id)
+    .bind(&token.token_hash)
+    .bind(token.expires_at)
+    .execute(pool)
+    .await
+    .map(|_| ())
+    .map_err(|e| {
+        AppError::new(
+            "Insert refresh token error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+/// Outcome of presenting a refresh token. \
+/// `Reused` - an already rotated token was presented, its family and session are revoked
+/// and the access tokens of the session `family` of `user_id` have to be deny-listed \
+/// `Rejected` - the token is expired, unknown or was revoked otherwise
+pub enum Rotation {
+    Rotated(String, RefreshToken),
+    Reused { family: Uuid, user_id: Uuid },
+    Rejected,
+}
+
+// revokes the presented token and issues its successor in the same family,
+// presenting an already rotated token revokes the whole family and its session
+#[instrument(name = "Rotating a refresh token.", skip(pool, token_hash), level = Level::INFO)]
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    token_hash: &str,
+    ttl: i64,
+) -> Result<Rotation, AppError> {
+    let mut transaction = pool.begin().await.map_err(|e| {
+        AppError::new(
+            "Rotate refresh token transaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    // only one of concurrent rotations of the same token can succeed
+    let current: Option<RefreshToken> = sqlx::query_as(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = NOW()
+        WHERE token_hash = $1 AND revoked_at IS NULL AND expires_at > NOW()
+        RETURNING id, family, user_id, token_hash, expires_at
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Rotate refresh token error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    let Some(current) = current else {
+        let revoked_family: Option<(Uuid, Uuid)> = sqlx::query_as(
+            "SELECT family, user_id FROM refresh_tokens WHERE token_hash = $1 AND revoked_at IS NOT NULL",
+        )
+        .bind(token_hash)
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(|e| {
+            AppError::new(
+                "Rotate refresh token error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })?;
+
+        let Some((family, user_id)) = revoked_family else {
+            return Ok(Rotation::Rejected);
+        };
+
+        warn!("Refresh token reuse detected, revoking family {}", family);
+        revoke_family(&mut transaction, family).await?;
+        transaction.commit().await.map_err(|e| {
+            AppError::new(
+                "Rotate refresh token transaction error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })?;
+
+        return Ok(Rotation::Reused { family, user_id });
+    };
+
+    let (token, next) = RefreshToken::new(current.user_id, Some(current.family), ttl);
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (id, family, user_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(next.id)
+    .bind(next.family)
+    .bind(next.user_id)
+    .bind(&next.token_hash)
+    .bind(next.expires_at)
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Insert refresh token error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    transaction.commit().await.map_err(|e| {
+        AppError::new(
+            "Rotate refresh token transaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    Ok(Rotation::Rotated(token, next))
+}
+
+// revokes every token of the login the presented token belongs to,
+// returns whether there was anything left to revoke
+#[instrument(name = "Revoking a refresh token.", skip(pool, token_hash), level = Level::INFO)]
+pub async fn revoke_refresh_token(
+    pool: &PgPool,
+    token_hash: &str,
+    user_id: Uuid,
+) -> Result<bool, AppError> {
+    sqlx::query(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = NOW()
+        WHERE family = (SELECT family FROM refresh_tokens WHERE token_hash = $1 AND user_id = $2)
+            AND revoked_at IS NULL
+        "#,
+    )
+    .bind(token_hash)
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+    .map_err(|e| {
+        AppError::new(
+            "Revoke refresh token error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// the family is the id of the session it belongs to
+async fn revoke_family(
+    connection: &mut sqlx::PgConnection,
+    family: Uuid,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        WITH tokens AS (
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE family = $1 AND revoked_at IS NULL
+        )
+        UPDATE sessions SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL
+        "#,
+    )
+    .bind(family)
+    .execute(connection)
+    .await
+    .map(|_| ())
+    .map_err(|e| {
+        AppError::new(
+            "Revoke refresh token family error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
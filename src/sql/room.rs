@@ -1,18 +1,259 @@
 use sqlx::{postgres::PgQueryResult, PgPool};
-use tracing::instrument;
+use tracing::{instrument, Level};
+use uuid::Uuid;
 
-use crate::{errors::AppError, graphql::room::schema::Room};
+use crate::{
+    errors::{AppError, AppErrorType},
+    graphql::room::schema::{Room, MESSAGE_PREVIEW_LENGTH},
+    ws::schema::MessageAuthor,
+};
 
-#[instrument(name = "")]
-pub async fn create_room(pool: &PgPool) -> Result<PgQueryResult, AppError> {
-    todo!()
-    // sqlx::query("
-    // INSERT INTO rooms
+// inserts the room and its creator as the first member (and admin) in one transaction
+#[instrument(name = "Creating a room.", skip(pool, room), fields(room.name = %room.name), level = Level::INFO)]
+pub async fn create_room(pool: &PgPool, room: Room, user_id: Uuid) -> Result<Room, AppError> {
+    let mut transaction = pool.begin().await.map_err(|e| {
+        AppError::new(
+            "Create room transaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
 
-    // ")
+    let room: Room = sqlx::query_as(
+        r#"
+        INSERT INTO rooms (id, name, description, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, name, description, created_at, updated_at, created_at AS last_activity_at
+        "#,
+    )
+    .bind(room.id)
+    .bind(room.name)
+    .bind(room.description)
+    .bind(room.created_at)
+    .bind(room.updated_at)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Insert room error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO room_users (room_id, user_id, is_admin)
+        VALUES ($1, $2, TRUE)
+        "#,
+    )
+    .bind(room.id)
+    .bind(user_id)
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Insert room user error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    transaction.commit().await.map_err(|e| {
+        AppError::new(
+            "Create room transaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    Ok(room)
+}
+
+#[instrument(name = "Getting a room.", skip(pool), level = Level::INFO)]
+pub async fn get_room(pool: &PgPool, id: Uuid) -> Result<Room, AppError> {
+    sqlx::query_as(
+        r#"
+        SELECT id, name, description, created_at, updated_at, created_at AS last_activity_at
+        FROM rooms WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => AppError::new(
+            format!("Room {} does not exist.", id),
+            AppErrorType::RoomNotFound,
+        ),
+        e => AppError::new(
+            "Get room error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        ),
+    })
+}
+
+// rooms of the user by last activity, `room_id` narrows it down to one room; unread and mention
+// counts are kept on the membership as messages are stored, deleted and read
+#[instrument(name = "Getting user rooms.", skip(pool), level = Level::INFO)]
+pub async fn get_rooms(
+    pool: &PgPool,
+    user_id: Uuid,
+    room_id: Option<Uuid>,
+) -> Result<Vec<Room>, AppError> {
+    sqlx::query_as(
+        r#"
+        SELECT r.id, r.name, r.description, r.created_at, r.updated_at,
+            lm.id AS last_message_id,
+            LEFT(lm.content, $3) AS last_message_preview,
+            COALESCE(lm.created_at, r.created_at) AS last_activity_at,
+            ru.unread_count, ru.mention_count
+        FROM room_users ru
+        INNER JOIN rooms r ON r.id = ru.room_id
+        LEFT JOIN LATERAL (
+            SELECT m.id, m.content, m.created_at
+            FROM messages m
+            WHERE m.room = r.id
+            ORDER BY m.created_at DESC, m.id DESC
+            LIMIT 1
+        ) lm ON TRUE
+        WHERE ru.user_id = $1 AND ($2::uuid IS NULL OR ru.room_id = $2)
+        ORDER BY last_activity_at DESC, r.id DESC
+        "#,
+    )
+    .bind(user_id)
+    .bind(room_id)
+    .bind(MESSAGE_PREVIEW_LENGTH)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Get rooms error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+#[instrument(name = "Getting room members.", skip(pool), level = Level::INFO)]
+pub async fn get_room_members(
+    pool: &PgPool,
+    room_id: Uuid,
+) -> Result<Vec<MessageAuthor>, AppError> {
+    sqlx::query_as(
+        r#"
+        SELECT u.id AS author_id, u.name AS author_name
+        FROM room_users ru
+        INNER JOIN users u ON u.id = ru.user_id
+        WHERE ru.room_id = $1
+        "#,
+    )
+    .bind(room_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Get room members error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
 }
 
-#[instrument(name = "")]
-pub async fn get_rooms(pool: &PgPool) -> Result<Vec<Room>, AppError> {
-    todo!()
-}
\ No newline at end of file
+#[instrument(name = "Getting user room ids.", skip(pool), level = Level::INFO)]
+pub async fn get_room_ids(pool: &PgPool, user_id: Uuid) -> Result<Vec<Uuid>, AppError> {
+    sqlx::query_scalar("SELECT room_id FROM room_users WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| {
+            AppError::new(
+                "Get room ids error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })
+}
+
+// joining a room twice is a no-op
+#[instrument(name = "Joining a room.", skip(pool), level = Level::INFO)]
+pub async fn join_room(
+    pool: &PgPool,
+    room_id: Uuid,
+    user_id: Uuid,
+) -> Result<PgQueryResult, AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO room_users (room_id, user_id)
+        VALUES ($1, $2)
+        ON CONFLICT (room_id, user_id) DO NOTHING
+        "#,
+    )
+    .bind(room_id)
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Join room error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+#[instrument(name = "Leaving a room.", skip(pool), level = Level::INFO)]
+pub async fn leave_room(
+    pool: &PgPool,
+    room_id: Uuid,
+    user_id: Uuid,
+) -> Result<PgQueryResult, AppError> {
+    sqlx::query(
+        r#"
+        DELETE FROM room_users
+        WHERE room_id = $1 AND user_id = $2
+        "#,
+    )
+    .bind(room_id)
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Leave room error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+#[instrument(name = "Checking room membership.", skip(pool), level = Level::INFO)]
+pub async fn is_room_member(pool: &PgPool, room_id: Uuid, user_id: Uuid) -> Result<bool, AppError> {
+    sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM room_users WHERE room_id = $1 AND user_id = $2)
+        "#,
+    )
+    .bind(room_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Check room membership error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+#[instrument(name = "Checking room admin.", skip(pool), level = Level::INFO)]
+pub async fn is_room_admin(pool: &PgPool, room_id: Uuid, user_id: Uuid) -> Result<bool, AppError> {
+    sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM room_users WHERE room_id = $1 AND user_id = $2 AND is_admin
+        )
+        "#,
+    )
+    .bind(room_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Check room admin error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
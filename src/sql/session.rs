@@ -0,0 +1,146 @@
+use sqlx::PgPool;
+use tracing::{instrument, Level};
+use uuid::Uuid;
+
+use crate::{
+    errors::{AppError, AppErrorType},
+    graphql::session::schema::Session,
+    service::session::SessionOrigin,
+};
+
+// the session id is also the family of its refresh tokens
+#[instrument(name = "Inserting a session.", skip(pool), level = Level::INFO)]
+pub async fn insert_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    origin: &SessionOrigin,
+) -> Result<Uuid, AppError> {
+    sqlx::query_scalar(
+        r#"
+        INSERT INTO sessions (id, user_id, device_label, ip, user_agent)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(&origin.device_label)
+    .bind(origin.ip.to_string())
+    .bind(&origin.user_agent)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Insert session error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+#[instrument(name = "Touching a session.", skip(pool), level = Level::INFO)]
+pub async fn touch_session(pool: &PgPool, id: Uuid) -> Result<(), AppError> {
+    sqlx::query("UPDATE sessions SET last_seen_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            AppError::new(
+                "Touch session error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })
+}
+
+// a session is active while one of its refresh tokens can still be used
+#[instrument(name = "Getting sessions.", skip(pool), level = Level::INFO)]
+pub async fn get_sessions(pool: &PgPool, user_id: Uuid) -> Result<Vec<Session>, AppError> {
+    sqlx::query_as(
+        r#"
+        SELECT s.id, s.device_label, s.ip, s.user_agent, s.created_at, s.last_seen_at
+        FROM sessions s
+        WHERE s.user_id = $1
+            AND s.revoked_at IS NULL
+            AND EXISTS (
+                SELECT 1 FROM refresh_tokens rt
+                WHERE rt.family = s.id AND rt.revoked_at IS NULL AND rt.expires_at > NOW()
+            )
+        ORDER BY s.last_seen_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Get sessions error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// revokes the session and its refresh tokens, returns whether it was active
+#[instrument(name = "Revoking a session.", skip(pool), level = Level::INFO)]
+pub async fn revoke_session(pool: &PgPool, user_id: Uuid, id: Uuid) -> Result<bool, AppError> {
+    sqlx::query_scalar::<_, Uuid>(
+        r#"
+        WITH revoked AS (
+            UPDATE sessions
+            SET revoked_at = NOW()
+            WHERE id = $2 AND user_id = $1 AND revoked_at IS NULL
+            RETURNING id
+        ), tokens AS (
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE family IN (SELECT id FROM revoked) AND revoked_at IS NULL
+        )
+        SELECT id FROM revoked
+        "#,
+    )
+    .bind(user_id)
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map(|revoked| revoked.is_some())
+    .map_err(|e| {
+        AppError::new(
+            "Revoke session error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// revokes every session of the user but `keep`, refresh tokens issued before sessions
+// existed are revoked as well; returns the revoked session ids
+#[instrument(name = "Revoking other sessions.", skip(pool), level = Level::INFO)]
+pub async fn revoke_other_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    keep: Option<Uuid>,
+) -> Result<Vec<Uuid>, AppError> {
+    sqlx::query_scalar(
+        r#"
+        WITH revoked AS (
+            UPDATE sessions
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND id IS DISTINCT FROM $2 AND revoked_at IS NULL
+            RETURNING id
+        ), tokens AS (
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE user_id = $1 AND family IS DISTINCT FROM $2 AND revoked_at IS NULL
+        )
+        SELECT id FROM revoked
+        "#,
+    )
+    .bind(user_id)
+    .bind(keep)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Revoke other sessions error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
@@ -0,0 +1,206 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use tracing::{instrument, Level};
+use uuid::Uuid;
+
+use crate::errors::{AppError, AppErrorType};
+
+/// `secret` is set from enrolment on, `enabled_at` once the first code is confirmed.
+#[derive(Debug, FromRow)]
+pub struct TotpState {
+    #[sqlx(rename = "totp_secret")]
+    pub secret: Option<String>,
+    #[sqlx(rename = "totp_enabled_at")]
+    pub enabled_at: Option<DateTime<Utc>>,
+}
+
+#[instrument(name = "Getting user totp.", skip(pool), level = Level::INFO)]
+pub async fn get_totp(pool: &PgPool, user_id: Uuid) -> Result<TotpState, AppError> {
+    sqlx::query_as("SELECT totp_secret, totp_enabled_at FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => AppError::new(
+                format!("User {} does not exist.", user_id),
+                AppErrorType::UserNotFound,
+            ),
+            e => AppError::new(
+                "Get user totp error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            ),
+        })
+}
+
+// replaces a pending secret, returns false when totp is already enabled
+#[instrument(name = "Setting user totp secret.", skip(pool, secret), level = Level::INFO)]
+pub async fn set_totp_secret(pool: &PgPool, user_id: Uuid, secret: &str) -> Result<bool, AppError> {
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_secret = $2, totp_last_step = NULL
+        WHERE id = $1 AND totp_enabled_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(secret)
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+    .map_err(|e| {
+        AppError::new(
+            "Set user totp secret error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// a code is accepted once, returns false when the step or a later one was already used
+#[instrument(name = "Using a totp step.", skip(pool), level = Level::INFO)]
+pub async fn use_totp_step(pool: &PgPool, user_id: Uuid, step: i64) -> Result<bool, AppError> {
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_last_step = $2
+        WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+        "#,
+    )
+    .bind(user_id)
+    .bind(step)
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+    .map_err(|e| {
+        AppError::new(
+            "Use totp step error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// enables totp and replaces the recovery codes in one transaction
+#[instrument(name = "Enabling user totp.", skip(pool, code_hashes), level = Level::INFO)]
+pub async fn enable_totp(pool: &PgPool, user_id: Uuid, code_hashes: &[String]) -> Result<(), AppError> {
+    let mut transaction = pool.begin().await.map_err(|e| {
+        AppError::new(
+            "Enable totp transaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    sqlx::query("UPDATE users SET totp_enabled_at = NOW() WHERE id = $1")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| {
+            AppError::new(
+                "Enable user totp error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })?;
+
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| {
+            AppError::new(
+                "Delete recovery codes error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })?;
+
+    let ids: Vec<Uuid> = code_hashes.iter().map(|_| Uuid::new_v4()).collect();
+    sqlx::query(
+        r#"
+        INSERT INTO recovery_codes (id, user_id, code_hash)
+        SELECT id, $2, code_hash FROM UNNEST($1::uuid[], $3::varchar[]) AS codes (id, code_hash)
+        "#,
+    )
+    .bind(&ids)
+    .bind(user_id)
+    .bind(code_hashes)
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Insert recovery codes error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    transaction.commit().await.map_err(|e| {
+        AppError::new(
+            "Enable totp transaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+#[instrument(name = "Disabling user totp.", skip(pool), level = Level::INFO)]
+pub async fn disable_totp(pool: &PgPool, user_id: Uuid) -> Result<(), AppError> {
+    let mut transaction = pool.begin().await.map_err(|e| {
+        AppError::new(
+            "Disable totp transaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Disable user totp error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(|e| {
+            AppError::new(
+                "Delete recovery codes error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })?;
+
+    transaction.commit().await.map_err(|e| {
+        AppError::new(
+            "Disable totp transaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// returns false when the code is unknown or already used
+#[instrument(name = "Using a recovery code.", skip(pool, code_hash), level = Level::INFO)]
+pub async fn use_recovery_code(pool: &PgPool, user_id: Uuid, code_hash: &str) -> Result<bool, AppError> {
+    sqlx::query(
+        r#"
+        UPDATE recovery_codes
+        SET used_at = NOW()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(code_hash)
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+    .map_err(|e| {
+        AppError::new(
+            "Use recovery code error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
@@ -4,11 +4,12 @@ use crate::{
 };
 use sqlx::PgPool;
 use tracing::{instrument, Level};
+use uuid::Uuid;
 
 #[instrument(name = "Getting a user.", skip(pool), level = Level::INFO)]
 pub async fn get_user(pool: &PgPool, email: &str) -> Result<User, AppError> {
     sqlx::query_as(
-        "SELECT id, email, name, password, created_at, updated_at FROM users WHERE email = $1",
+        "SELECT id, email, name, password, created_at, updated_at, verified_at, totp_enabled_at, bot FROM users WHERE email = $1",
     )
     .bind(email)
     .fetch_one(pool)
@@ -21,14 +22,34 @@ pub async fn get_user(pool: &PgPool, email: &str) -> Result<User, AppError> {
     })
 }
 
+#[instrument(name = "Getting a user by id.", skip(pool), level = Level::INFO)]
+pub async fn get_user_by_id(pool: &PgPool, id: Uuid) -> Result<User, AppError> {
+    sqlx::query_as(
+        "SELECT id, email, name, password, created_at, updated_at, verified_at, totp_enabled_at, bot FROM users WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => AppError::new(
+            format!("User {} does not exist.", id),
+            AppErrorType::UserNotFound,
+        ),
+        e => AppError::new(
+            "Get user error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        ),
+    })
+}
+
 // skip user, context but include user.name
 #[instrument(name = "Creating a user.", skip(pool, user), fields(user.name = %user.name), level = Level::INFO)]
 pub async fn insert_user(pool: &PgPool, user: User) -> Result<User, AppError> {
     sqlx::query_as(
         r#"
-        INSERT INTO users (id, email, name, password, created_at, updated_at)
-        VALUES ($1, $2, $3, $4, $5, $6)
-        RETURNING id, name, password, email, created_at, updated_at"#,
+        INSERT INTO users (id, email, name, password, created_at, updated_at, bot)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, name, password, email, created_at, updated_at, verified_at, totp_enabled_at, bot"#,
     )
     .bind(user.id)
     .bind(user.email)
@@ -36,6 +57,7 @@ pub async fn insert_user(pool: &PgPool, user: User) -> Result<User, AppError> {
     .bind(user.password)
     .bind(user.created_at)
     .bind(user.updated_at)
+    .bind(user.bot)
     .fetch_one(pool)
     .await
     .map_err(|e| {
@@ -53,7 +75,7 @@ pub async fn update_user(pool: &PgPool, user: UserUpdate) -> Result<User, AppErr
         UPDATE users
         SET name = $2, updated_at = $3
         WHERE id = $1
-        RETURNING id, name, email, password, created_at, updated_at
+        RETURNING id, name, email, password, created_at, updated_at, verified_at, totp_enabled_at, bot
         "#,
     )
     .bind(user.id)
@@ -68,3 +90,66 @@ pub async fn update_user(pool: &PgPool, user: UserUpdate) -> Result<User, AppErr
         )
     })
 }
+
+#[instrument(name = "Checking user admin.", skip(pool), level = Level::INFO)]
+pub async fn is_admin(pool: &PgPool, id: Uuid) -> Result<bool, AppError> {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE id = $1 AND is_admin)")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| {
+            AppError::new(
+                "Check user admin error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })
+}
+
+#[instrument(name = "Updating a user password.", skip(pool, password), level = Level::INFO)]
+pub async fn update_password(pool: &PgPool, id: Uuid, password: String) -> Result<(), AppError> {
+    sqlx::query("UPDATE users SET password = $2, updated_at = $3 WHERE id = $1")
+        .bind(id)
+        .bind(password)
+        .bind(chrono::Utc::now())
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| {
+            AppError::new(
+                "Update user password error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })
+}
+
+// returns whether the email was not verified before
+#[instrument(name = "Verifying a user email.", skip(pool), level = Level::INFO)]
+pub async fn verify_email(pool: &PgPool, id: Uuid) -> Result<bool, AppError> {
+    sqlx::query("UPDATE users SET verified_at = NOW() WHERE id = $1 AND verified_at IS NULL")
+        .bind(id)
+        .execute(pool)
+        .await
+        .map(|result| result.rows_affected() > 0)
+        .map_err(|e| {
+            AppError::new(
+                "Verify user email error.".to_string(),
+                AppErrorType::DatabaseError(e),
+            )
+        })
+}
+
+#[instrument(name = "Finding a user by email.", skip(pool), level = Level::INFO)]
+pub async fn find_user_by_email(pool: &PgPool, email: &str) -> Result<Option<User>, AppError> {
+    sqlx::query_as(
+        "SELECT id, email, name, password, created_at, updated_at, verified_at, totp_enabled_at, bot FROM users WHERE email = $1",
+    )
+    .bind(email)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Find user error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
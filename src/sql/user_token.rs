@@ -0,0 +1,86 @@
+use sqlx::PgPool;
+use tracing::{instrument, Level};
+
+use crate::{
+    crypt::user_token::UserTokenClaims,
+    errors::{AppError, AppErrorType},
+};
+
+// earlier unused tokens of the same purpose stop working once a new one is issued
+#[instrument(name = "Inserting a user token.", skip(pool, claims), fields(claims.sub = %claims.sub), level = Level::INFO)]
+pub async fn insert_user_token(pool: &PgPool, claims: &UserTokenClaims) -> Result<(), AppError> {
+    let mut transaction = pool.begin().await.map_err(|e| {
+        AppError::new(
+            "Insert user token transaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    sqlx::query(
+        r#"
+        UPDATE user_tokens
+        SET used_at = NOW()
+        WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(claims.sub)
+    .bind(claims.purpose.as_str())
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Invalidate user tokens error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_tokens (id, user_id, purpose, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(claims.jti)
+    .bind(claims.sub)
+    .bind(claims.purpose.as_str())
+    .bind(claims.expires_at())
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| {
+        AppError::new(
+            "Insert user token error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })?;
+
+    transaction.commit().await.map_err(|e| {
+        AppError::new(
+            "Insert user token transaction error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
+
+// marks the token used, returns false when it was already used, replaced or expired
+#[instrument(name = "Consuming a user token.", skip(pool, claims), fields(claims.sub = %claims.sub), level = Level::INFO)]
+pub async fn consume_user_token(pool: &PgPool, claims: &UserTokenClaims) -> Result<bool, AppError> {
+    sqlx::query(
+        r#"
+        UPDATE user_tokens
+        SET used_at = NOW()
+        WHERE id = $1 AND user_id = $2 AND purpose = $3 AND used_at IS NULL AND expires_at > NOW()
+        "#,
+    )
+    .bind(claims.jti)
+    .bind(claims.sub)
+    .bind(claims.purpose.as_str())
+    .execute(pool)
+    .await
+    .map(|result| result.rows_affected() > 0)
+    .map_err(|e| {
+        AppError::new(
+            "Consume user token error.".to_string(),
+            AppErrorType::DatabaseError(e),
+        )
+    })
+}
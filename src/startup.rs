@@ -1,21 +1,29 @@
+use axum::extract::State;
 use axum::routing::{on, MethodFilter};
 use redis::aio::ConnectionManager;
 use sqlx::PgPool;
-use tokio::sync::broadcast;
 use tokio::{join, signal};
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use uuid::Uuid;
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::net::TcpListener;
 
 use crate::configuration::RedisWorkerConfig;
-use crate::errors::AppError;
-use crate::graphql::handlers::{graphql, login, playground, register};
+use crate::errors::{AppError, AppErrorType};
+use crate::crypt::{api_token::ApiTokenScope, keys::JwtKeys, token::Claims};
+use crate::graphql::handlers::{
+    graphql, graphql_ws, jwks, login, login_two_factor, logout, oidc_callback, oidc_login,
+    playground, refresh_token, register, request_password_reset_handler, reset_password_handler,
+    verify_email_handler,
+};
+use crate::service::mailer::Mailer;
 use crate::graphql::root::{create_schema, Schema};
 use crate::service::worker::RedisWorker;
-use crate::ws::ws::ws_handler;
+use crate::sql::user::is_admin;
+use crate::ws::fanout::RoomFanout;
+use crate::ws::rooms::ChatRooms;
+use crate::ws::ws::{sweep_presence, ws_handler};
 use axum::{
     routing::{get, post},
     Router,
@@ -25,57 +33,110 @@ pub async fn health_check() -> Result<String, ()> {
     Ok("Hello World!".to_string())
 }
 
+// live room gauges of this instance in the Prometheus text format,
+// for admins and api tokens of admins with the `metrics:read` scope
+pub async fn metrics(State(state): State<AppState>, claims: Claims) -> Result<String, AppError> {
+    let user_id = claims.scoped_user_id(ApiTokenScope::MetricsRead)?;
+    if !is_admin(&state.pool, user_id).await? {
+        return Err(AppError::new(
+            format!("User {} is not an admin.", user_id),
+            AppErrorType::ForbiddenError("Admin only".to_string()),
+        ));
+    }
+
+    Ok(format!(
+        "# TYPE fast_chat_live_rooms gauge\nfast_chat_live_rooms {}\n\
+         # TYPE fast_chat_live_sockets gauge\nfast_chat_live_sockets {}\n",
+        state.chats.live_rooms(),
+        state.chats.live_sockets()
+    ))
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub pool: PgPool,
     pub redis: ConnectionManager,
     pub schema: Arc<Schema>,
-    pub chats: Arc<Mutex<HashMap<Uuid, broadcast::Sender<Vec<u8>>>>>,
+    pub chats: ChatRooms,
+    pub keys: JwtKeys,
+    pub mailer: Arc<dyn Mailer>,
 }
 
 impl AppState {
-    pub fn initialize(pool: PgPool, redis: ConnectionManager) -> Result<Self, AppError> {
+    pub fn initialize(
+        pool: PgPool,
+        redis: ConnectionManager,
+        chats: ChatRooms,
+        keys: JwtKeys,
+        mailer: Arc<dyn Mailer>,
+    ) -> Result<Self, AppError> {
         let schema = Arc::new(create_schema());
         Ok(Self {
             pool,
             redis,
             schema,
-            chats: Arc::new(Mutex::new(HashMap::default())),
+            chats,
+            keys,
+            mailer,
         })
     }
 }
 
-pub async fn run(listener: TcpListener, db_pool: PgPool, redis: ConnectionManager, redis_worker_config: RedisWorkerConfig) {
-    let app_state = AppState::initialize(db_pool.clone(), redis.clone()).expect("Failed to initialize app state.");
+pub async fn run(
+    listener: TcpListener,
+    db_pool: PgPool,
+    redis: ConnectionManager,
+    redis_client: redis::Client,
+    redis_worker_config: RedisWorkerConfig,
+    keys: JwtKeys,
+    mailer: Arc<dyn Mailer>,
+) {
+    let (fanout, fanout_commands) = RoomFanout::new();
+    let chats = ChatRooms::new(Some(fanout.clone()));
+    fanout.listen(fanout_commands, redis.clone(), redis_client, chats.clone());
+
+    let app_state = AppState::initialize(db_pool.clone(), redis.clone(), chats, keys, mailer).expect("Failed to initialize app state.");
+    tokio::spawn(sweep_presence(app_state.clone()));
 
     let app = Router::new()
         .layer(CorsLayer::new().allow_credentials(true))
         .layer(TraceLayer::new_for_http())
         .route("/", get(health_check))
+        .route("/metrics", get(metrics))
         .route("/login", post(login))
+        .route("/login/2fa", post(login_two_factor))
+        .route("/oidc/login", get(oidc_login))
+        .route("/oidc/callback", get(oidc_callback))
         .route("/register", post(register))
+        .route("/token/refresh", post(refresh_token))
+        .route("/logout", post(logout))
+        .route("/.well-known/jwks.json", get(jwks))
+        .route("/password/reset/request", post(request_password_reset_handler))
+        .route("/password/reset", post(reset_password_handler))
+        .route("/email/verify", post(verify_email_handler))
         .route("/ws/:room", get(ws_handler))
         .route(
             "/graphql",
             on(MethodFilter::GET.or(MethodFilter::POST), graphql),
         )
+        .route("/graphql/ws", get(graphql_ws))
         .route("/graphiql", get(playground))
         .with_state(app_state);
 
     let http = async {
-        axum::serve(listener, app.into_make_service())
+        // peer addresses name the client of a session and the login throttle, unless they are
+        // trusted proxies which forward it in a header
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
             .with_graceful_shutdown(shutdown_signal())
             .await
             .unwrap()
     };
 
     let background = async {
-        RedisWorker::new(redis.clone(), db_pool.clone(), redis_worker_config)
-     };
+        RedisWorker::new(redis.clone(), db_pool.clone(), redis_worker_config).spawn_worker()
+    };
 
     join!(http, background);
-
-    ()
 }
 
 async fn shutdown_signal() {
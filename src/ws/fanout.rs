@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use redis::{
+    aio::{ConnectionManager, PubSub},
+    AsyncCommands, Client, Msg, RedisResult,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::ws::rooms::ChatRooms;
+
+pub static ROOM_CHANNEL_PREFIX: &str = "fast-chat:room:";
+pub static RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+pub static RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+pub fn room_channel(room: Uuid) -> String {
+    format!("{}{}", ROOM_CHANNEL_PREFIX, room)
+}
+
+enum FanoutCommand {
+    Subscribe(Uuid),
+    Unsubscribe(Uuid),
+    Publish(Uuid, Vec<u8>),
+}
+
+/// Receiving end of the fan-out commands, handed to `RoomFanout::listen`.
+pub struct FanoutCommands(UnboundedReceiver<FanoutCommand>);
+
+/// Fan-out of room broadcasts between instances over Redis pub/sub. \
+/// Every published payload is prefixed with the 16 bytes of the publishing instance id,
+/// so an instance can skip its own echoes which were already delivered locally.
+#[derive(Clone)]
+pub struct RoomFanout {
+    instance: Uuid,
+    commands: UnboundedSender<FanoutCommand>,
+}
+
+impl RoomFanout {
+    pub fn new() -> (Self, FanoutCommands) {
+        let (commands, receiver) = mpsc::unbounded_channel();
+
+        (
+            RoomFanout {
+                instance: Uuid::new_v4(),
+                commands,
+            },
+            FanoutCommands(receiver),
+        )
+    }
+
+    pub fn subscribe(&self, room: Uuid) {
+        let _ = self.commands.send(FanoutCommand::Subscribe(room));
+    }
+
+    pub fn unsubscribe(&self, room: Uuid) {
+        let _ = self.commands.send(FanoutCommand::Unsubscribe(room));
+    }
+
+    pub fn publish(&self, room: Uuid, payload: &[u8]) {
+        let mut message = Vec::with_capacity(16 + payload.len());
+        message.extend_from_slice(self.instance.as_bytes());
+        message.extend_from_slice(payload);
+        let _ = self.commands.send(FanoutCommand::Publish(room, message));
+    }
+
+    // runs the commands and delivers payloads published by other instances to the local room
+    // channels, all in a single task so publishes keep their order; a dropped pub/sub
+    // connection is reopened with backoff and subscribed to every room with local sockets
+    pub fn listen(
+        &self,
+        commands: FanoutCommands,
+        redis: ConnectionManager,
+        client: Client,
+        chats: ChatRooms,
+    ) {
+        let instance = self.instance;
+        tokio::spawn(async move {
+            let FanoutCommands(mut commands) = commands;
+            let mut redis = redis;
+            let mut rooms = HashSet::new();
+            let mut delay = RECONNECT_BASE_DELAY;
+
+            loop {
+                match connect(&client, &rooms).await {
+                    Ok(pubsub) => {
+                        delay = RECONNECT_BASE_DELAY;
+                        let (mut sink, mut stream) = pubsub.split();
+                        loop {
+                            tokio::select! {
+                                command = commands.recv() => {
+                                    let Some(command) = command else { return };
+                                    let result = match command {
+                                        FanoutCommand::Subscribe(room) => {
+                                            rooms.insert(room);
+                                            sink.subscribe(room_channel(room)).await
+                                        }
+                                        FanoutCommand::Unsubscribe(room) => {
+                                            rooms.remove(&room);
+                                            sink.unsubscribe(room_channel(room)).await
+                                        }
+                                        FanoutCommand::Publish(room, message) => {
+                                            publish(&mut redis, room, message).await
+                                        }
+                                    };
+                                    if let Err(e) = result {
+                                        warn!("Room fan-out command failed: {}", e);
+                                    }
+                                }
+                                message = stream.next() => match message {
+                                    Some(message) => deliver(&chats, instance, message),
+                                    None => break,
+                                },
+                            }
+                        }
+                        warn!("Room fan-out subscription closed, reconnecting.");
+                    }
+                    Err(e) => warn!("Room fan-out connection failed: {}", e),
+                }
+
+                // commands keep running while waiting, rooms joined meanwhile are subscribed
+                // on reconnect; broadcasts of other instances in between are missed
+                let wait = time::sleep(delay);
+                tokio::pin!(wait);
+                loop {
+                    tokio::select! {
+                        _ = &mut wait => break,
+                        command = commands.recv() => match command {
+                            Some(FanoutCommand::Subscribe(room)) => {
+                                rooms.insert(room);
+                            }
+                            Some(FanoutCommand::Unsubscribe(room)) => {
+                                rooms.remove(&room);
+                            }
+                            Some(FanoutCommand::Publish(room, message)) => {
+                                if let Err(e) = publish(&mut redis, room, message).await {
+                                    warn!("Room fan-out command failed: {}", e);
+                                }
+                            }
+                            None => return,
+                        },
+                    }
+                }
+                delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+            }
+        });
+    }
+}
+
+async fn connect(client: &Client, rooms: &HashSet<Uuid>) -> RedisResult<PubSub> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    if !rooms.is_empty() {
+        let channels: Vec<String> = rooms.iter().map(|room| room_channel(*room)).collect();
+        pubsub.subscribe(channels).await?;
+        info!("Room fan-out subscribed to {} rooms.", rooms.len());
+    }
+
+    Ok(pubsub)
+}
+
+async fn publish(redis: &mut ConnectionManager, room: Uuid, message: Vec<u8>) -> RedisResult<()> {
+    redis.publish(room_channel(room), message).await
+}
+
+fn deliver(chats: &ChatRooms, instance: Uuid, message: Msg) {
+    let Some(room) = message
+        .get_channel_name()
+        .strip_prefix(ROOM_CHANNEL_PREFIX)
+        .and_then(|room| Uuid::parse_str(room).ok())
+    else {
+        return;
+    };
+
+    let payload = message.get_payload_bytes();
+    if payload.len() < 16 || payload[..16] == instance.as_bytes()[..] {
+        return;
+    }
+
+    chats.deliver(room, payload[16..].to_vec());
+}
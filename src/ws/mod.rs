@@ -1,2 +1,5 @@
+pub mod fanout;
+pub mod rooms;
 pub mod schema;
+#[allow(clippy::module_inception)]
 pub mod ws;
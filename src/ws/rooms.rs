@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+use uuid::Uuid;
+
+use crate::ws::{fanout::RoomFanout, schema::SocketMessage};
+
+struct RoomEntry {
+    tx: Sender<Vec<u8>>,
+    subscribers: usize,
+}
+
+/// Per-room broadcast channels of the live sockets on this instance,
+/// kept in sync with other instances through `fanout`. \
+/// A room entry lives as long as at least one `RoomSubscription` of it does.
+#[derive(Clone, Default)]
+pub struct ChatRooms {
+    channels: Arc<Mutex<HashMap<Uuid, RoomEntry>>>,
+    sockets: Arc<AtomicUsize>,
+    fanout: Option<RoomFanout>,
+}
+
+impl ChatRooms {
+    // without fanout broadcasts only reach sockets of this instance
+    pub fn new(fanout: Option<RoomFanout>) -> Self {
+        ChatRooms {
+            fanout,
+            ..Default::default()
+        }
+    }
+
+    // subscribes to the room channel, creating it for the first subscriber
+    pub fn join(&self, room: Uuid) -> RoomSubscription {
+        let mut channels = self.channels.lock().expect("Failed to lock for chats.");
+        let entry = channels.entry(room).or_insert_with(|| {
+            if let Some(fanout) = &self.fanout {
+                fanout.subscribe(room);
+            }
+            let (tx, _rx) = broadcast::channel(100);
+            RoomEntry { tx, subscribers: 0 }
+        });
+        entry.subscribers += 1;
+        self.sockets.fetch_add(1, Ordering::Relaxed);
+
+        RoomSubscription {
+            chats: self.clone(),
+            room,
+            rx: entry.tx.subscribe(),
+        }
+    }
+
+    // removes the room channel once its last subscriber is gone
+    fn leave(&self, room: Uuid) {
+        let mut channels = self.channels.lock().expect("Failed to lock for chats.");
+        if let Some(entry) = channels.get_mut(&room) {
+            entry.subscribers -= 1;
+            if entry.subscribers == 0 {
+                channels.remove(&room);
+                if let Some(fanout) = &self.fanout {
+                    fanout.unsubscribe(room);
+                }
+            }
+        }
+        self.sockets.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // reaches the room sockets of every instance
+    pub fn broadcast(&self, room: Uuid, message: &SocketMessage) {
+        let payload = serde_json::to_vec(message).unwrap();
+        if let Some(fanout) = &self.fanout {
+            fanout.publish(room, &payload);
+        }
+        self.deliver(room, payload);
+    }
+
+    // reaches the room sockets of this instance, no-op when nobody is connected
+    pub fn deliver(&self, room: Uuid, payload: Vec<u8>) {
+        let channels = self.channels.lock().expect("Failed to lock for chats.");
+        if let Some(entry) = channels.get(&room) {
+            let _ = entry.tx.send(payload);
+        }
+    }
+
+    // gauge of rooms with at least one subscriber on this instance
+    pub fn live_rooms(&self) -> usize {
+        self.channels.lock().expect("Failed to lock for chats.").len()
+    }
+
+    // gauge of room subscriptions (sockets) on this instance
+    pub fn live_sockets(&self) -> usize {
+        self.sockets.load(Ordering::Relaxed)
+    }
+}
+
+/// Receiving end of a room channel, leaves the room when dropped.
+pub struct RoomSubscription {
+    chats: ChatRooms,
+    room: Uuid,
+    rx: Receiver<Vec<u8>>,
+}
+
+impl RoomSubscription {
+    pub async fn recv(&mut self) -> Result<Vec<u8>, RecvError> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for RoomSubscription {
+    fn drop(&mut self) {
+        self.chats.leave(self.room);
+    }
+}
@@ -1,23 +1,133 @@
 use derivative::Derivative;
+use juniper::{GraphQLEnum, GraphQLObject};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, Type};
 use uuid::Uuid;
 
-use crate::graphql::user::schema::User;
-
 #[derive(Serialize, Deserialize, Debug)]
 pub enum SocketMessage {
-    Send(SocketMessageContent),
-    Update(SocketMessageContent),
+    Send(SocketMessageInput),
+    Update(SocketMessageUpdate),
     Delete(Vec<Uuid>),
     Seen(Vec<Uuid>),
+    AddReaction(SocketReactionInput),
+    RemoveReaction(SocketReactionInput),
     Typing,
     Ping,
     Pong,
     Close,
+    // server-only, a message built from a client `Send`
+    Message(SocketMessageContent),
+    // server-only, a message built from a client `Send` with a `parent_id`
+    ThreadReply(SocketMessageContent),
+    // server-only, the user has been removed from the room
+    MemberRemoved(Uuid),
+    // server-only, the sessions have been revoked, their sockets are closed
+    SessionRevoked(Vec<Uuid>),
+    // server-only, a client `AddReaction` / `RemoveReaction` with its author
+    ReactionAdded(SocketReaction),
+    ReactionRemoved(SocketReaction),
+    // server-only, the read cursor of a user advanced by a client `Seen`
+    ReadReceipt(SocketReadReceipt),
+    // server-only, a client `Typing` with its author
+    UserTyping(MessageAuthor),
+    // server-only, the first socket of a user opened or the last one closed
+    PresenceChanged(PresenceChange),
+    // server-only, sent to the client whose event was refused
+    Rejected(SocketRejection),
+}
+
+/// MessageInput - what a client is allowed to send \
+/// `content` - content of the message \
+/// `nonce` - optional client generated value echoed back in the broadcast message \
+/// `parent_id` - Uuid of the message replied to in a thread
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SocketMessageInput {
+    pub content: String,
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
+}
+
+/// MessageUpdate \
+/// `id` - Uuid of the edited message \
+/// `content` - new content of the message
+#[derive(Serialize, Deserialize, Debug, Clone, GraphQLObject)]
+#[graphql(name = "MessageUpdate")]
+pub struct SocketMessageUpdate {
+    pub id: Uuid,
+    pub content: String,
+}
+
+/// ReactionInput \
+/// `id` - Uuid of the message reacted to \
+/// `emoji` - a single emoji
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SocketReactionInput {
+    pub id: Uuid,
+    pub emoji: String,
+}
+
+/// Reaction \
+/// `id` - Uuid of the message reacted to \
+/// `emoji` - a single emoji \
+/// `user` - who reacted \
+/// `created_at` - when the reaction was added or removed, the latest of both wins
+#[derive(Serialize, Deserialize, Debug, Clone, GraphQLObject)]
+#[graphql(name = "Reaction")]
+pub struct SocketReaction {
+    pub id: Uuid,
+    pub emoji: String,
+    pub user: MessageAuthor,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// ReadReceipt \
+/// `user` - who read the room \
+/// `room` - Uuid of the room \
+/// `message_id` / `message_created_at` - newest message read, every older one is read as well \
+/// `read_at` - when the user read it
+#[derive(Serialize, Deserialize, Debug, Clone, GraphQLObject)]
+#[graphql(name = "ReadReceipt")]
+pub struct SocketReadReceipt {
+    pub user: MessageAuthor,
+    pub room: Uuid,
+    pub message_id: Uuid,
+    pub message_created_at: chrono::DateTime<chrono::Utc>,
+    pub read_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Rejection \
+/// `ids` - Uuids of the messages the event was refused for \
+/// `reason` - why the event was refused
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SocketRejection {
+    pub ids: Vec<Uuid>,
+    pub reason: String,
+}
+
+/// PresenceChange \
+/// `user` - user whose presence in the room changed \
+/// `online` - whether the user has a socket open in the room
+#[derive(Serialize, Deserialize, Debug, Clone, GraphQLObject)]
+pub struct PresenceChange {
+    pub user: MessageAuthor,
+    pub online: bool,
+}
+
+/// Public part of the user attached to messages.
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow, GraphQLObject, Derivative)]
+#[derivative(Default)]
+pub struct MessageAuthor {
+    #[derivative(Default(value = "Uuid::new_v4()"))]
+    #[sqlx(rename = "author_id")]
+    pub id: Uuid,
+    #[sqlx(rename = "author_name")]
+    pub name: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Type, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Type, GraphQLEnum, Default)]
+#[repr(i16)]
 pub enum MessageStatus {
     #[default]
     NotSent = 1,
@@ -30,7 +140,10 @@ pub enum MessageStatus {
 /// `content` - content of the message \
 /// `author` - author (creator, sender) of the message \
 /// `room_id` - Uuid of the room where message has been sent \
-/// `status` - status of message, whether its been sent or seen by the users
+/// `status` - status of message, whether its been sent, who has read it is kept per user \
+/// `parent_id` - Uuid of the thread root for replies \
+/// `reply_count` / `last_reply_at` - replies of a thread root \
+/// `nonce` - client nonce of the `Send` this message was built from, never persisted
 #[derive(Serialize, Deserialize, Debug, Clone, FromRow, Derivative)]
 #[derivative(Default)]
 pub struct SocketMessageContent {
@@ -38,9 +151,18 @@ pub struct SocketMessageContent {
     pub id: Uuid,
     pub content: String,
     #[sqlx(flatten)]
-    pub author: User,
+    pub author: MessageAuthor,
     pub room: Uuid,
     pub status: MessageStatus,
     #[derivative(Default(value = "chrono::Utc::now()"))]
     pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<Uuid>,
+    #[serde(default)]
+    pub reply_count: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_reply_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
 }
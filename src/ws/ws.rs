@@ -1,6 +1,20 @@
-use crate::service::stream::{AsyncEvent, EventRedisStream, ASYNC_EVENT_DELETE, ASYNC_EVENT_MARK_AS_SEEN, ASYNC_EVENT_SEND, ASYNC_EVENT_UPDATE};
-use crate::ws::schema::SocketMessage;
-use crate::{crypt::token::Claims, errors::AppError, startup::AppState};
+use crate::graphql::message::validators::ReactionEmoji;
+use crate::service::stream::{AsyncEvent, EventRedisStream, ASYNC_EVENT_MARK_AS_SEEN, ASYNC_EVENT_MESSAGE, ASYNC_EVENT_REACTION};
+use crate::service::mention::mentioned_members;
+use crate::sql::message::{get_latest_message, get_message, get_message_authors, insert_message};
+use crate::sql::read_cursor::advances_read_cursor;
+use crate::sql::room::{get_room, is_room_admin, is_room_member};
+use crate::sql::user::get_user_by_id;
+use crate::service::presence::{Presence, PRESENCE_HEARTBEAT};
+use crate::ws::schema::{
+    MessageAuthor, MessageStatus, PresenceChange, SocketMessage, SocketMessageContent,
+    SocketReaction, SocketReactionInput, SocketReadReceipt, SocketRejection,
+};
+use crate::{
+    crypt::{api_token::ApiTokenScope, token::Claims},
+    errors::{AppError, AppErrorType},
+    startup::AppState,
+};
 use axum::extract::ws::Message;
 use axum::{
     extract::{
@@ -12,130 +26,483 @@ use axum::{
 use axum_macros::debug_handler;
 use futures_util::stream::StreamExt;
 use futures_util::SinkExt;
-use redis::aio::ConnectionManager;
 use std::ops::ControlFlow;
-use tokio::sync::broadcast::{self, Sender};
-use tracing::info;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::time;
+use tracing::{info, warn};
 use uuid::Uuid;
 
 #[debug_handler]
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
-    _claims: Claims,
+    claims: Claims,
     State(state): State<AppState>,
     Path(room): Path<Uuid>,
 ) -> Result<Response, AppError> {
-    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, room)))
+    let user = claims.scoped_user_id(ApiTokenScope::MessagesRead)?;
+    claims.require_room(room)?;
+    let can_write = claims.require_scope(ApiTokenScope::MessagesWrite).is_ok();
+
+    // 404 for unknown rooms, 403 for rooms the user is not a member of
+    get_room(&state.pool, room).await?;
+    if !is_room_member(&state.pool, room, user).await? {
+        return Err(AppError::new(
+            format!("User {} is not a member of room {}.", user, room),
+            AppErrorType::ForbiddenError("Not a member of the room".to_string()),
+        ));
+    }
+
+    let user = get_user_by_id(&state.pool, user).await?;
+    let author = MessageAuthor {
+        id: user.id,
+        name: user.name,
+    };
+
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, room, author, claims.sid, can_write)))
 }
 
-pub async fn handle_socket(socket: WebSocket, state: AppState, chat: Uuid) {
+pub async fn handle_socket(
+    socket: WebSocket,
+    state: AppState,
+    chat: Uuid,
+    author: MessageAuthor,
+    sid: Option<Uuid>,
+    can_write: bool,
+) {
+    let user = author.id;
     let (mut sender, mut receiver) = socket.split();
 
-    let tx = {
-        let mut chats = state.chats.lock().expect("Failed to lock for chats.");
-        match chats.get(&chat) {
-            Some(chat) => chat.clone(),
-            None => {
-                let (tx, _rx) = broadcast::channel(100);
-                chats.insert(chat.clone(), tx.clone());
-                tx
-            }
-        }
-    };
+    // owned by the send task, the room is left as soon as either task ends
+    let mut subscription = state.chats.join(chat);
 
-    let mut rx = tx.subscribe();
+    let chats = state.chats.clone();
+    let mut presence = Presence::new(state.redis.clone());
+    let present = author.clone();
+    let socket = Uuid::new_v4();
+    match presence.connect(chat, user, socket).await {
+        Ok(true) => chats.broadcast(
+            chat,
+            &SocketMessage::PresenceChanged(PresenceChange {
+                user: present.clone(),
+                online: true,
+            }),
+        ),
+        Ok(false) => {}
+        Err(e) => warn!("Presence update failed: {}", e),
+    }
+
+    // events addressed to this socket only
+    let (direct_tx, mut direct_rx) = mpsc::unbounded_channel::<Vec<u8>>();
 
     let mut send_task = tokio::spawn(async move {
-        while let Ok(message) = rx.recv().await {
+        loop {
+            let message = tokio::select! {
+                Ok(message) = subscription.recv() => message,
+                Some(message) = direct_rx.recv() => message,
+                else => break,
+            };
+
+            let (removed, revoked) = match serde_json::from_slice::<SocketMessage>(&message) {
+                Ok(SocketMessage::MemberRemoved(id)) => (id == user, false),
+                // other sessions of the user share the room, their ids are not forwarded
+                Ok(SocketMessage::SessionRevoked(sids)) => {
+                    if !sid.is_some_and(|sid| sids.contains(&sid)) {
+                        continue;
+                    }
+                    (false, true)
+                }
+                _ => (false, false),
+            };
             if sender.send(Message::Binary(message)).await.is_err() {
                 break;
             }
+            if removed || revoked {
+                let _ = sender.send(Message::Close(None)).await;
+                break;
+            }
         }
     });
 
+    let session = SocketSession {
+        state,
+        chat,
+        author,
+        can_write,
+        direct_tx,
+    };
+
     let mut recv_task: tokio::task::JoinHandle<()> = tokio::spawn(async move {
         while let Some(Ok(Message::Binary(message))) = receiver.next().await {
-            if process_message(message, &tx, state.redis.clone()).is_break() {
+            // membership may have been revoked since the upgrade
+            match is_room_member(&session.state.pool, session.chat, session.author.id).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("User {} is no longer a member of room {}", session.author.id, session.chat);
+                    return;
+                }
+                Err(e) => {
+                    warn!("Membership check failed: {}", e);
+                    return;
+                }
+            }
+
+            if session.process_message(message).await.is_break() {
                 return;
             }
         }
     });
 
+    let mut beat = presence.clone();
+    let heartbeat = async move {
+        let mut interval = time::interval(PRESENCE_HEARTBEAT);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(e) = beat.heartbeat(chat, user, socket).await {
+                warn!("Presence heartbeat failed: {}", e);
+            }
+        }
+    };
+
     tokio::select! {
         _ = (&mut send_task) => recv_task.abort(),
         _ = (&mut recv_task) => send_task.abort(),
+        _ = heartbeat => {}
     };
+
+    match presence.disconnect(chat, user, socket).await {
+        Ok(true) => chats.broadcast(
+            chat,
+            &SocketMessage::PresenceChanged(PresenceChange {
+                user: present,
+                online: false,
+            }),
+        ),
+        Ok(false) => {}
+        Err(e) => warn!("Presence update failed: {}", e),
+    }
+}
+
+// announces the users of expired sockets offline, every instance sweeps and
+// the removal of each socket is won by a single one of them
+pub async fn sweep_presence(state: AppState) {
+    let mut presence = Presence::new(state.redis.clone());
+    let mut interval = time::interval(PRESENCE_HEARTBEAT);
+    loop {
+        interval.tick().await;
+        let offline = match presence.sweep().await {
+            Ok(offline) => offline,
+            Err(e) => {
+                warn!("Presence sweep failed: {}", e);
+                continue;
+            }
+        };
+
+        for (room, user) in offline {
+            match get_user_by_id(&state.pool, user).await {
+                Ok(user) => state.chats.broadcast(
+                    room,
+                    &SocketMessage::PresenceChanged(PresenceChange {
+                        user: MessageAuthor {
+                            id: user.id,
+                            name: user.name,
+                        },
+                        online: false,
+                    }),
+                ),
+                Err(e) => warn!("Presence sweep failed: {}", e),
+            }
+        }
+    }
 }
 
-fn process_message(
-    msg: Vec<u8>,
-    tx: &Sender<Vec<u8>>,
-    redis_connection_manager: ConnectionManager,
-) -> ControlFlow<(), ()> {
-    if let Ok(msg) = serde_json::from_slice::<SocketMessage>(&msg) {
-        match msg {
-            SocketMessage::Send(message) => {
-                let _ = tx.send(serde_json::to_vec(&SocketMessage::Send(message.clone())).unwrap());
-
-                tokio::spawn(async move {
-                    EventRedisStream::new(
-                        ASYNC_EVENT_SEND,
-                        redis_connection_manager,
-                    )
-                    .add_to_stream(AsyncEvent::Send(message.clone()))
-                    .await
-                });
-            }
-            SocketMessage::Seen(ids) => {
-                // TODO: decide whether to notify other users that message was seen
-                // let _ tx.send(serde_json::to_vec())
-
-                tokio::spawn(async move {
-                    EventRedisStream::new(
-                        ASYNC_EVENT_MARK_AS_SEEN,
-                        redis_connection_manager,
-                    )
-                    .add_to_stream(AsyncEvent::MarkAsSeen(ids.clone()))
-                    .await
-                });
-            }
-            SocketMessage::Update(message) => {
-                let _ =
-                    tx.send(serde_json::to_vec(&SocketMessage::Update(message.clone())).unwrap());
-
-                tokio::spawn(async move {
-                    EventRedisStream::new(
-                        ASYNC_EVENT_UPDATE,
-                        redis_connection_manager,
-                    )
-                    .add_to_stream(AsyncEvent::Update(message.clone()))
-                    .await
-                });
-            }
-            SocketMessage::Delete(ids) => {
-                let _ = tx.send(serde_json::to_vec(&SocketMessage::Delete(ids.clone())).unwrap());
-
-                tokio::spawn(async move {
-                    EventRedisStream::new(ASYNC_EVENT_DELETE, redis_connection_manager)
-                        .add_to_stream(AsyncEvent::Delete(ids.clone()))
-                        .await
-                });
-            }
-            SocketMessage::Pong => {
-                let _ = tx.send(serde_json::to_vec(&SocketMessage::Pong).unwrap());
-            }
-            // health check
-            SocketMessage::Ping => {
-                let _ = tx.send(serde_json::to_vec(&SocketMessage::Pong).unwrap());
-            }
-            SocketMessage::Typing => {
-                let _ = tx.send(serde_json::to_vec(&msg).unwrap());
-            }
-            SocketMessage::Close => return ControlFlow::Break(()),
+/// Connection of a single user to a single room.
+struct SocketSession {
+    state: AppState,
+    chat: Uuid,
+    author: MessageAuthor,
+    // false for api tokens without the `messages:write` scope
+    can_write: bool,
+    direct_tx: UnboundedSender<Vec<u8>>,
+}
+
+impl SocketSession {
+    async fn process_message(&self, msg: Vec<u8>) -> ControlFlow<(), ()> {
+        if let Ok(msg) = serde_json::from_slice::<SocketMessage>(&msg) {
+            if !self.can_write
+                && matches!(
+                    msg,
+                    SocketMessage::Send(_)
+                        | SocketMessage::Update(_)
+                        | SocketMessage::Delete(_)
+                        | SocketMessage::AddReaction(_)
+                        | SocketMessage::RemoveReaction(_)
+                )
+            {
+                self.reply(&SocketMessage::Rejected(SocketRejection {
+                    ids: Vec::new(),
+                    reason: "Missing scope messages:write.".to_string(),
+                }));
+                return ControlFlow::Continue(());
+            }
+
+            match msg {
+                SocketMessage::Send(input) => {
+                    if let Some(parent_id) = input.parent_id {
+                        if !self.is_thread_root(parent_id).await {
+                            self.reject(vec![parent_id], "Replies need a thread root of this room.");
+                            return ControlFlow::Continue(());
+                        }
+                    }
+
+                    // everything but the content and the thread comes from the server
+                    let message = SocketMessageContent {
+                        id: Uuid::new_v4(),
+                        content: input.content,
+                        author: self.author.clone(),
+                        room: self.chat,
+                        status: MessageStatus::Sent,
+                        created_at: chrono::Utc::now(),
+                        parent_id: input.parent_id,
+                        nonce: input.nonce,
+                        ..Default::default()
+                    };
+
+                    // persisted before the broadcast, edits, deletions, reactions and replies
+                    // right after it are authorized against the stored message
+                    if let Err(e) = self.persist(&message).await {
+                        warn!("Message {} could not be stored: {}", message.id, e);
+                        self.reject(vec![message.id], "Message could not be sent.");
+                        return ControlFlow::Continue(());
+                    }
+
+                    // clients without thread panes only show thread roots
+                    if message.parent_id.is_some() {
+                        self.broadcast(&SocketMessage::ThreadReply(message));
+                    } else {
+                        self.broadcast(&SocketMessage::Message(message));
+                    }
+                }
+                SocketMessage::Seen(ids) => {
+                    if let Some(receipt) = self.read_receipt(ids).await {
+                        self.broadcast(&SocketMessage::ReadReceipt(receipt.clone()));
+                        self.add_to_stream(ASYNC_EVENT_MARK_AS_SEEN, AsyncEvent::MarkAsRead(receipt)).await;
+                    }
+                }
+                SocketMessage::Update(message) => match self.partition_ids(vec![message.id], true).await {
+                    Ok((allowed, _)) if !allowed.is_empty() => {
+                        self.broadcast(&SocketMessage::Update(message.clone()));
+                        self.add_to_stream(ASYNC_EVENT_MESSAGE, AsyncEvent::Update(message)).await;
+                    }
+                    _ => self.reject(
+                        vec![message.id],
+                        "Only the author or a room admin can edit this message.",
+                    ),
+                },
+                SocketMessage::Delete(ids) => {
+                    let (allowed, rejected) = match self.partition_ids(ids, true).await {
+                        Ok(partition) => partition,
+                        Err(rejected) => (Vec::new(), rejected),
+                    };
+                    self.reject(rejected, "Only the author or a room admin can delete this message.");
+
+                    if !allowed.is_empty() {
+                        self.broadcast(&SocketMessage::Delete(allowed.clone()));
+                        self.add_to_stream(ASYNC_EVENT_MESSAGE, AsyncEvent::Delete(allowed)).await;
+                    }
+                }
+                SocketMessage::AddReaction(input) => {
+                    if let Some(reaction) = self.reaction(input).await {
+                        self.broadcast(&SocketMessage::ReactionAdded(reaction.clone()));
+                        self.add_to_stream(ASYNC_EVENT_REACTION, AsyncEvent::AddReaction(reaction))
+                            .await;
+                    }
+                }
+                SocketMessage::RemoveReaction(input) => {
+                    if let Some(reaction) = self.reaction(input).await {
+                        self.broadcast(&SocketMessage::ReactionRemoved(reaction.clone()));
+                        self.add_to_stream(ASYNC_EVENT_REACTION, AsyncEvent::RemoveReaction(reaction))
+                            .await;
+                    }
+                }
+                SocketMessage::Pong => {
+                    self.broadcast(&SocketMessage::Pong);
+                }
+                // health check
+                SocketMessage::Ping => {
+                    self.broadcast(&SocketMessage::Pong);
+                }
+                SocketMessage::Typing => {
+                    self.broadcast(&SocketMessage::UserTyping(self.author.clone()));
+                }
+                SocketMessage::Close => return ControlFlow::Break(()),
+                // server-only events are never accepted from clients
+                SocketMessage::Message(_)
+                | SocketMessage::ThreadReply(_)
+                | SocketMessage::MemberRemoved(_)
+                | SocketMessage::SessionRevoked(_)
+                | SocketMessage::ReactionAdded(_)
+                | SocketMessage::ReactionRemoved(_)
+                | SocketMessage::ReadReceipt(_)
+                | SocketMessage::UserTyping(_)
+                | SocketMessage::PresenceChanged(_)
+                | SocketMessage::Rejected(_) => {}
+            }
+        } else {
+            info!("Couldn't deserialize message");
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    // splits ids into (allowed, rejected), ids outside of the room are always rejected,
+    // with `owned` only the author's messages are allowed unless the user is a room admin
+    async fn partition_ids(
+        &self,
+        ids: Vec<Uuid>,
+        owned: bool,
+    ) -> Result<(Vec<Uuid>, Vec<Uuid>), Vec<Uuid>> {
+        let pool = &self.state.pool;
+        let authors = match get_message_authors(pool, &ids, self.chat).await {
+            Ok(authors) => authors,
+            Err(e) => {
+                warn!("Message authorization failed: {}", e);
+                return Err(ids);
+            }
+        };
+
+        let needs_admin = owned && authors.iter().any(|(_, author)| *author != self.author.id);
+        let is_admin = if needs_admin {
+            match is_room_admin(pool, self.chat, self.author.id).await {
+                Ok(is_admin) => is_admin,
+                Err(e) => {
+                    warn!("Message authorization failed: {}", e);
+                    return Err(ids);
+                }
+            }
+        } else {
+            false
+        };
+
+        Ok(ids.into_iter().partition(|id| {
+            authors.iter().any(|(message, author)| {
+                message == id && (!owned || is_admin || *author == self.author.id)
+            })
+        }))
+    }
+
+    async fn persist(&self, message: &SocketMessageContent) -> Result<(), AppError> {
+        let pool = &self.state.pool;
+        let mentioned = mentioned_members(pool, message).await?;
+        insert_message(pool, message.clone(), &mentioned).await?;
+
+        Ok(())
+    }
+
+    // replies of replies are not allowed, threads are one level deep
+    async fn is_thread_root(&self, id: Uuid) -> bool {
+        match get_message(&self.state.pool, id).await {
+            Ok(message) => message.room == self.chat && message.parent_id.is_none(),
+            Err(e) => {
+                info!("Thread root {} not found: {}", id, e);
+                false
+            }
+        }
+    }
+
+    // the reaction of this user when the emoji is valid and the message is in the room,
+    // the client is told otherwise
+    async fn reaction(&self, input: SocketReactionInput) -> Option<SocketReaction> {
+        let emoji = match ReactionEmoji::parse(input.emoji) {
+            Ok(emoji) => emoji.inner(),
+            Err(_) => {
+                self.reject(vec![input.id], "Not a valid reaction.");
+                return None;
+            }
+        };
+
+        match self.partition_ids(vec![input.id], false).await {
+            Ok((allowed, _)) if !allowed.is_empty() => Some(SocketReaction {
+                id: input.id,
+                emoji,
+                user: self.author.clone(),
+                created_at: chrono::Utc::now(),
+            }),
+            _ => {
+                self.reject(vec![input.id], "Message not found in this room.");
+                None
+            }
+        }
+    }
+
+    // reading a message reads every older one, the receipt points at the newest of `ids`
+    // which belongs to the room, the client is told about the others; none is made when
+    // the stored cursor of the user is already there
+    async fn read_receipt(&self, ids: Vec<Uuid>) -> Option<SocketReadReceipt> {
+        let (ids, rejected) = match self.partition_ids(ids, false).await {
+            Ok(partition) => partition,
+            Err(rejected) => (Vec::new(), rejected),
+        };
+        self.reject(rejected, "Message not found in this room.");
+        if ids.is_empty() {
+            return None;
+        }
+
+        let receipt = match get_latest_message(&self.state.pool, &ids, self.chat).await {
+            Ok(Some((message_id, message_created_at))) => SocketReadReceipt {
+                user: self.author.clone(),
+                room: self.chat,
+                message_id,
+                message_created_at,
+                read_at: chrono::Utc::now(),
+            },
+            Ok(None) => {
+                self.reject(ids, "Message not found in this room.");
+                return None;
+            }
+            Err(e) => {
+                warn!("Read receipt failed: {}", e);
+                self.reject(ids, "Message not found in this room.");
+                return None;
+            }
+        };
+
+        match advances_read_cursor(&self.state.pool, &receipt).await {
+            Ok(true) => Some(receipt),
+            Ok(false) => None,
+            Err(e) => {
+                warn!("Read receipt failed: {}", e);
+                None
+            }
         }
-    } else {
-        info!("Couldn't deserialize message");
     }
 
-    ControlFlow::Continue(())
+    fn broadcast(&self, message: &SocketMessage) {
+        self.state.chats.broadcast(self.chat, message);
+    }
+
+    fn reject(&self, ids: Vec<Uuid>, reason: &str) {
+        if ids.is_empty() {
+            return;
+        }
+
+        self.reply(&SocketMessage::Rejected(SocketRejection {
+            ids,
+            reason: reason.to_string(),
+        }));
+    }
+
+    fn reply(&self, message: &SocketMessage) {
+        let _ = self.direct_tx.send(serde_json::to_vec(message).unwrap());
+    }
+
+    // awaited so the events of a socket reach their stream in the order they were made
+    async fn add_to_stream(&self, stream_key: &'static str, event: AsyncEvent) {
+        let result = EventRedisStream::new(stream_key, self.state.redis.clone())
+            .add_to_stream(event)
+            .await;
+        if let Err(e) = result {
+            warn!("Adding to stream {} failed: {}", stream_key, e);
+        }
+    }
 }
@@ -0,0 +1,180 @@
+mod common;
+
+use fast_chat::{
+    configuration::{Argon2Settings, MailSettings, MailTransportSettings, PasswordPolicySettings},
+    crypt::{
+        token::{decode_token, encode_token, is_token_revoked, Claims},
+        user_token::{TokenPurpose, UserTokenClaims},
+    },
+    errors::AppError,
+    graphql::user::schema::{SessionUser, User},
+    service::{
+        account::{request_password_reset, reset_password},
+        mailer::InMemoryMailer,
+        session::SessionOrigin,
+    },
+    sql::{
+        session::{get_sessions, insert_session},
+        user::insert_user,
+    },
+    startup::AppState,
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+fn mail_settings() -> MailSettings {
+    MailSettings {
+        sender: "chat@example.com".to_string(),
+        base_url: "http://localhost:3000".to_string(),
+        verification_token_max_age: 60,
+        password_reset_token_max_age: 60,
+        require_verified_email: false,
+        transport: MailTransportSettings::Memory,
+    }
+}
+
+fn password_policy() -> PasswordPolicySettings {
+    PasswordPolicySettings {
+        min_length: 8,
+        max_length: 128,
+        require_lowercase: false,
+        require_uppercase: false,
+        require_digit: false,
+        require_symbol: false,
+    }
+}
+
+fn argon2_settings() -> Argon2Settings {
+    Argon2Settings {
+        memory_cost: 4096,
+        time_cost: 1,
+        parallelism: 1,
+    }
+}
+
+async fn alice(pool: &PgPool) -> User {
+    insert_user(
+        pool,
+        User {
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            password: "not-a-hash".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("User should be inserted.")
+}
+
+// the token of the reset email sent to the user
+async fn reset_token(state: &AppState, user: &User) -> String {
+    let mailer = InMemoryMailer::default();
+    request_password_reset(&state.pool, &state.keys, &mailer, &mail_settings(), &user.email)
+        .await
+        .expect("Reset should be requested.");
+    let email = mailer.sent().pop().expect("Reset email should be sent.");
+    assert_eq!(email.to, user.email);
+
+    email
+        .body
+        .split("token=")
+        .nth(1)
+        .and_then(|rest| rest.split_whitespace().next())
+        .expect("Reset email should contain a token.")
+        .to_string()
+}
+
+async fn reset(state: &AppState, token: &str, password: &str) -> Result<(), AppError> {
+    reset_password(
+        state,
+        token,
+        password.to_string(),
+        &password_policy(),
+        &argon2_settings(),
+        5,
+    )
+    .await
+}
+
+#[sqlx::test]
+async fn password_reset_token_works_only_once(pool: PgPool) {
+    let state = common::test_state(pool).await;
+    let user = alice(&state.pool).await;
+    let token = reset_token(&state, &user).await;
+
+    assert!(reset(&state, &token, "first new password").await.is_ok());
+    assert!(reset(&state, &token, "second new password").await.is_err());
+}
+
+#[sqlx::test]
+async fn password_reset_ends_every_session(pool: PgPool) {
+    let state = common::test_state(pool).await;
+    let user = alice(&state.pool).await;
+    let origin = SessionOrigin {
+        ip: "127.0.0.1".parse().unwrap(),
+        user_agent: None,
+        device_label: None,
+    };
+    let sid = insert_session(&state.pool, user.id, &origin)
+        .await
+        .expect("Session should be inserted.");
+    let claims = Claims::new(
+        &SessionUser {
+            id: user.id,
+            email: &user.email,
+        },
+        sid,
+        5,
+    );
+
+    let token = reset_token(&state, &user).await;
+    reset(&state, &token, "new password").await.unwrap();
+
+    assert!(get_sessions(&state.pool, user.id).await.unwrap().is_empty());
+    assert!(is_token_revoked(&mut state.redis.clone(), &claims)
+        .await
+        .unwrap());
+}
+
+#[sqlx::test]
+async fn password_reset_of_an_unknown_email_sends_nothing(pool: PgPool) {
+    let mailer = InMemoryMailer::default();
+
+    request_password_reset(
+        &pool,
+        &common::test_keys(),
+        &mailer,
+        &mail_settings(),
+        "nobody@example.com",
+    )
+    .await
+    .expect("Unknown emails should not be reported.");
+    assert!(mailer.sent().is_empty());
+}
+
+#[test]
+fn tokens_are_only_accepted_for_their_own_audience() {
+    let keys = common::test_keys();
+    let user_id = Uuid::new_v4();
+    let reset = UserTokenClaims::new(user_id, TokenPurpose::ResetPassword, 5)
+        .encode(&keys)
+        .unwrap();
+    let access = encode_token(
+        &keys,
+        &Claims::new(
+            &SessionUser {
+                id: user_id,
+                email: "alice@example.com",
+            },
+            Uuid::new_v4(),
+            5,
+        ),
+    )
+    .unwrap();
+
+    assert!(decode_token(&keys, &access).is_ok());
+    assert!(decode_token(&keys, &reset).is_err());
+    assert!(UserTokenClaims::decode(&keys, &reset, TokenPurpose::ResetPassword).is_ok());
+    assert!(UserTokenClaims::decode(&keys, &reset, TokenPurpose::VerifyEmail).is_err());
+    assert!(UserTokenClaims::decode(&keys, &access, TokenPurpose::TwoFactorLogin).is_err());
+}
@@ -0,0 +1,41 @@
+use fast_chat::ws::{rooms::ChatRooms, schema::SocketMessage};
+use uuid::Uuid;
+
+#[test]
+fn rooms_are_removed_when_the_last_subscriber_leaves() {
+    let chats = ChatRooms::new(None);
+    let rooms: Vec<Uuid> = (0..1000).map(|_| Uuid::new_v4()).collect();
+
+    let subscriptions: Vec<_> = rooms
+        .iter()
+        .flat_map(|room| [chats.join(*room), chats.join(*room)])
+        .collect();
+    assert_eq!(chats.live_rooms(), 1000);
+    assert_eq!(chats.live_sockets(), 2000);
+
+    drop(subscriptions);
+    assert_eq!(chats.live_rooms(), 0);
+    assert_eq!(chats.live_sockets(), 0);
+}
+
+#[tokio::test]
+async fn room_is_kept_while_a_subscriber_remains() {
+    let chats = ChatRooms::new(None);
+    let room = Uuid::new_v4();
+
+    let first = chats.join(room);
+    let mut second = chats.join(room);
+    drop(first);
+    assert_eq!(chats.live_rooms(), 1);
+    assert_eq!(chats.live_sockets(), 1);
+
+    chats.broadcast(room, &SocketMessage::Typing);
+    let payload = second.recv().await.expect("Broadcast should reach the subscriber.");
+    assert!(matches!(
+        serde_json::from_slice::<SocketMessage>(&payload),
+        Ok(SocketMessage::Typing)
+    ));
+
+    drop(second);
+    assert_eq!(chats.live_rooms(), 0);
+}
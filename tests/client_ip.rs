@@ -0,0 +1,54 @@
+use std::net::IpAddr;
+
+use axum::http::{HeaderMap, HeaderValue};
+use fast_chat::service::session::client_ip;
+
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().unwrap()
+}
+
+fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        headers.append(*name, HeaderValue::from_static(value));
+    }
+    headers
+}
+
+#[test]
+fn forwarding_headers_of_untrusted_peers_are_ignored() {
+    let headers = headers(&[("x-forwarded-for", "198.51.100.7")]);
+
+    assert_eq!(client_ip(ip("203.0.113.9"), &headers, &[]), ip("203.0.113.9"));
+    assert_eq!(
+        client_ip(ip("203.0.113.9"), &headers, &[ip("10.0.0.1")]),
+        ip("203.0.113.9")
+    );
+}
+
+#[test]
+fn client_is_the_first_untrusted_hop_from_the_right() {
+    let proxies = [ip("10.0.0.1"), ip("10.0.0.2")];
+    // the leftmost entry is made up by the client
+    let headers = headers(&[("x-forwarded-for", "1.1.1.1, 198.51.100.7, 10.0.0.2")]);
+
+    assert_eq!(client_ip(ip("10.0.0.1"), &headers, &proxies), ip("198.51.100.7"));
+}
+
+#[test]
+fn forwarded_header_takes_precedence() {
+    let headers = headers(&[
+        ("x-forwarded-for", "198.51.100.7"),
+        ("forwarded", "for=192.0.2.60;proto=https, for=\"[2001:db8::1]:4711\""),
+    ]);
+
+    assert_eq!(client_ip(ip("10.0.0.1"), &headers, &[ip("10.0.0.1")]), ip("2001:db8::1"));
+}
+
+#[test]
+fn trusted_peer_without_headers_is_the_client() {
+    assert_eq!(
+        client_ip(ip("10.0.0.1"), &HeaderMap::new(), &[ip("10.0.0.1")]),
+        ip("10.0.0.1")
+    );
+}
@@ -0,0 +1,53 @@
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use fast_chat::{
+    configuration::{JwtKeySettings, JwtSettings},
+    crypt::keys::JwtKeys,
+    service::mailer::InMemoryMailer,
+    startup::AppState,
+    ws::rooms::ChatRooms,
+};
+use sqlx::PgPool;
+
+pub static TEST_KID: &str = "test";
+pub static TEST_PRIVATE_KEY_PATH: &str = "tests/fixtures/jwt_private_key.pem";
+pub static TEST_PUBLIC_KEY_PATH: &str = "tests/fixtures/jwt_public_key.pem";
+
+// keys signing and verifying every token of a test, not used anywhere else
+pub fn test_keys() -> JwtKeys {
+    JwtKeys::from_settings(&JwtSettings {
+        signing_kid: TEST_KID.to_string(),
+        keys: vec![JwtKeySettings {
+            kid: TEST_KID.to_string(),
+            public_key: None,
+            public_key_path: Some(TEST_PUBLIC_KEY_PATH.to_string()),
+            private_key: None,
+            private_key_path: Some(TEST_PRIVATE_KEY_PATH.to_string()),
+        }],
+    })
+    .expect("Test keys should load.")
+}
+
+// a running Redis, `REDIS_URL` or the default local one
+pub async fn test_redis() -> redis::aio::ConnectionManager {
+    let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+    redis::Client::open(url)
+        .expect("Redis url should be valid.")
+        .get_connection_manager()
+        .await
+        .expect("Redis should be running.")
+}
+
+// state of a single instance on the test database, the test keys and a running Redis
+pub async fn test_state(pool: PgPool) -> AppState {
+    AppState::initialize(
+        pool,
+        test_redis().await,
+        ChatRooms::new(None),
+        test_keys(),
+        Arc::new(InMemoryMailer::default()),
+    )
+    .expect("App state should initialize.")
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use axum::{routing::get, Router};
+use fast_chat::{
+    crypt::token::{encode_token, Claims},
+    graphql::{handlers::graphql_ws, user::schema::SessionUser},
+    startup::AppState,
+};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::net::TcpListener;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, http::HeaderValue, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+use uuid::Uuid;
+
+type Socket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+async fn open(state: AppState) -> Socket {
+    let app = Router::new()
+        .route("/graphql/ws", get(graphql_ws))
+        .with_state(state);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address: SocketAddr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let mut request = format!("ws://{}/graphql/ws", address)
+        .into_client_request()
+        .unwrap();
+    request.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        HeaderValue::from_static("graphql-transport-ws"),
+    );
+    let (socket, _) = connect_async(request)
+        .await
+        .expect("Subscription endpoint should upgrade.");
+
+    socket
+}
+
+async fn init(socket: &mut Socket, payload: Value) -> Option<Message> {
+    let init = json!({ "type": "connection_init", "payload": payload });
+    socket.send(Message::Text(init.to_string())).await.unwrap();
+
+    socket.next().await.and_then(Result::ok)
+}
+
+#[sqlx::test]
+async fn subscription_endpoint_acks_an_authorized_connection(pool: PgPool) {
+    let state = common::test_state(pool).await;
+    let user = SessionUser {
+        id: Uuid::new_v4(),
+        email: "alice@example.com",
+    };
+    let token = encode_token(&state.keys, &Claims::new(&user, Uuid::new_v4(), 5)).unwrap();
+    let mut socket = open(state).await;
+
+    let reply = init(&mut socket, json!({ "Authorization": format!("Bearer {}", token) })).await;
+
+    let Some(Message::Text(reply)) = reply else {
+        panic!("Expected a text reply, got {:?}", reply);
+    };
+    let reply: Value = serde_json::from_str(&reply).unwrap();
+    assert_eq!(reply["type"], "connection_ack");
+}
+
+#[sqlx::test]
+async fn subscription_endpoint_closes_an_unauthorized_connection(pool: PgPool) {
+    let mut socket = open(common::test_state(pool).await).await;
+
+    let reply = init(&mut socket, json!({})).await;
+
+    assert!(
+        matches!(reply, Some(Message::Close(_)) | None),
+        "Expected the connection to close, got {:?}",
+        reply
+    );
+}
@@ -0,0 +1,113 @@
+use fast_chat::{
+    graphql::{room::schema::Room, user::schema::User},
+    service::mention::{mentioned_members, mentions},
+    sql::{
+        message::{delete_messages, insert_message},
+        read_cursor::advance_read_cursor,
+        room::{create_room, get_rooms, join_room},
+        user::insert_user,
+    },
+    ws::schema::{MessageAuthor, SocketMessageContent, SocketReadReceipt},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[test]
+fn mentions_match_whole_names_only() {
+    assert!(mentions("hi @alice", "alice"));
+    assert!(mentions("@Alice, look", "alice"));
+    assert!(mentions("ask @Alice Smith.", "alice smith"));
+    assert!(!mentions("hi @alice", "al"));
+    assert!(!mentions("mail bob@alice", "alice"));
+    assert!(!mentions("hi alice", "alice"));
+}
+
+async fn member(pool: &PgPool, name: &str) -> MessageAuthor {
+    let user = insert_user(
+        pool,
+        User {
+            name: name.to_string(),
+            email: format!("{}@example.com", name),
+            password: "not-a-hash".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("User should be inserted.");
+
+    MessageAuthor {
+        id: user.id,
+        name: user.name,
+    }
+}
+
+async fn send(
+    pool: &PgPool,
+    room: Uuid,
+    author: &MessageAuthor,
+    content: &str,
+) -> SocketMessageContent {
+    let message = SocketMessageContent {
+        content: content.to_string(),
+        author: author.clone(),
+        room,
+        ..Default::default()
+    };
+    let mentioned = mentioned_members(pool, &message)
+        .await
+        .expect("Mentions should be read.");
+    insert_message(pool, message.clone(), &mentioned)
+        .await
+        .expect("Message should be inserted.");
+
+    message
+}
+
+async fn counts(pool: &PgPool, room: Uuid, user: &MessageAuthor) -> (i32, i32) {
+    let rooms = get_rooms(pool, user.id, Some(room))
+        .await
+        .expect("Rooms should be read.");
+
+    (rooms[0].unread_count, rooms[0].mention_count)
+}
+
+#[sqlx::test]
+async fn unread_and_mention_counts_follow_messages_and_reads(pool: PgPool) {
+    let alice = member(&pool, "alice").await;
+    let al = member(&pool, "al").await;
+    let room = create_room(
+        &pool,
+        Room {
+            name: "general".to_string(),
+            ..Default::default()
+        },
+        alice.id,
+    )
+    .await
+    .expect("Room should be created.")
+    .id;
+    join_room(&pool, room, al.id).await.unwrap();
+
+    send(&pool, room, &alice, "hi @al").await;
+    let latest = send(&pool, room, &alice, "hi @alice").await;
+    let mention_of_alice = send(&pool, room, &al, "hi @alice").await;
+    assert_eq!(counts(&pool, room, &al).await, (2, 1));
+    assert_eq!(counts(&pool, room, &alice).await, (1, 1));
+
+    delete_messages(&pool, vec![mention_of_alice.id]).await.unwrap();
+    assert_eq!(counts(&pool, room, &alice).await, (0, 0));
+
+    advance_read_cursor(
+        &pool,
+        SocketReadReceipt {
+            user: al.clone(),
+            room,
+            message_id: latest.id,
+            message_created_at: latest.created_at,
+            read_at: chrono::Utc::now(),
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(counts(&pool, room, &al).await, (0, 0));
+}
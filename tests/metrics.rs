@@ -0,0 +1,89 @@
+mod common;
+
+use std::net::SocketAddr;
+
+use axum::{routing::get, Router};
+use fast_chat::{
+    crypt::token::{encode_token, Claims},
+    graphql::user::schema::{SessionUser, User},
+    sql::user::insert_user,
+    startup::{metrics, AppState},
+};
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+async fn serve(state: AppState) -> String {
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(state);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address: SocketAddr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    format!("http://{}/metrics", address)
+}
+
+// a login of a new user, returns its access token
+async fn login(state: &AppState, admin: bool) -> String {
+    let user = insert_user(
+        &state.pool,
+        User {
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            password: "not-a-hash".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("User should be inserted.");
+    sqlx::query("UPDATE users SET is_admin = $2 WHERE id = $1")
+        .bind(user.id)
+        .bind(admin)
+        .execute(&state.pool)
+        .await
+        .unwrap();
+
+    let session_user = SessionUser {
+        id: user.id,
+        email: &user.email,
+    };
+    encode_token(&state.keys, &Claims::new(&session_user, Uuid::new_v4(), 5)).unwrap()
+}
+
+async fn status(url: &str, token: Option<&str>) -> StatusCode {
+    let mut request = reqwest::Client::new().get(url);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+
+    request.send().await.expect("Metrics should answer.").status()
+}
+
+#[sqlx::test]
+async fn metrics_need_a_token(pool: PgPool) {
+    let url = serve(common::test_state(pool).await).await;
+
+    assert_eq!(status(&url, None).await, StatusCode::UNAUTHORIZED);
+}
+
+#[sqlx::test]
+async fn metrics_are_only_served_to_admins(pool: PgPool) {
+    let state = common::test_state(pool).await;
+    let token = login(&state, false).await;
+    let url = serve(state).await;
+
+    assert_eq!(status(&url, Some(&token)).await, StatusCode::FORBIDDEN);
+}
+
+#[sqlx::test]
+async fn metrics_are_served_to_admins(pool: PgPool) {
+    let state = common::test_state(pool).await;
+    let token = login(&state, true).await;
+    let url = serve(state).await;
+
+    assert_eq!(status(&url, Some(&token)).await, StatusCode::OK);
+}
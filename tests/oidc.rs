@@ -0,0 +1,228 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+    Form, Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use fast_chat::{
+    configuration::{Argon2Settings, OidcSettings},
+    crypt::keys::JwtKeys,
+    graphql::user::schema::User,
+    service::oidc::{sign_in, IdTokenClaims, OidcClient},
+    sql::user::{insert_user, verify_email},
+};
+use reqwest::Url;
+use secrecy::Secret;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+static CLIENT_ID: &str = "fast-chat";
+
+/// Issuer serving discovery, JWKS and a token endpoint which checks the PKCE verifier
+/// against the challenge of the authorization url. \
+/// `nonce` - overrides the nonce of the id token
+#[derive(Clone)]
+struct MockIssuer {
+    url: String,
+    keys: JwtKeys,
+    challenge: Arc<Mutex<Option<String>>>,
+    nonce: Arc<Mutex<Option<String>>>,
+}
+
+impl MockIssuer {
+    async fn spawn() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = MockIssuer {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            keys: common::test_keys(),
+            challenge: Arc::new(Mutex::new(None)),
+            nonce: Arc::new(Mutex::new(None)),
+        };
+
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .with_state(issuer.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        issuer
+    }
+
+    fn settings(&self) -> OidcSettings {
+        OidcSettings {
+            issuer: self.url.clone(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: Secret::new("secret".to_string()),
+            scopes: vec!["openid".to_string(), "email".to_string()],
+            redirect_url: "http://localhost/oidc/callback".to_string(),
+        }
+    }
+
+    // what the user agent would carry to the provider: the state, nonce and PKCE challenge
+    fn authorize(&self, authorization_url: &str) -> (String, String) {
+        let params: HashMap<String, String> = Url::parse(authorization_url)
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+        assert_eq!(params["code_challenge_method"], "S256");
+        *self.challenge.lock().unwrap() = Some(params["code_challenge"].clone());
+        self.nonce
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| params["nonce"].clone());
+
+        (params["state"].clone(), "code".to_string())
+    }
+}
+
+async fn discovery(State(issuer): State<MockIssuer>) -> Json<Value> {
+    Json(json!({
+        "issuer": issuer.url,
+        "authorization_endpoint": format!("{}/authorize", issuer.url),
+        "token_endpoint": format!("{}/token", issuer.url),
+        "jwks_uri": format!("{}/jwks", issuer.url),
+    }))
+}
+
+async fn jwks(State(issuer): State<MockIssuer>) -> Json<Value> {
+    Json(serde_json::to_value(issuer.keys.jwks()).unwrap())
+}
+
+async fn token(
+    State(issuer): State<MockIssuer>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    let challenge = issuer.challenge.lock().unwrap().clone();
+    let verifier = form.get("code_verifier").ok_or(StatusCode::BAD_REQUEST)?;
+    if challenge != Some(URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let id_token = issuer
+        .keys
+        .encode(&json!({
+            "iss": issuer.url,
+            "aud": CLIENT_ID,
+            "sub": "subject",
+            "iat": now,
+            "exp": now + 300,
+            "email": "alice@example.com",
+            "email_verified": true,
+            "name": "alice",
+            "nonce": issuer.nonce.lock().unwrap().clone(),
+        }))
+        .unwrap();
+
+    Ok(Json(json!({ "id_token": id_token, "token_type": "Bearer" })))
+}
+
+fn claims(email_verified: bool) -> IdTokenClaims {
+    IdTokenClaims {
+        iss: "https://issuer.example.com".to_string(),
+        sub: "subject".to_string(),
+        email: Some("alice@example.com".to_string()),
+        email_verified: Some(email_verified),
+        name: Some("alice".to_string()),
+        preferred_username: None,
+        nonce: None,
+    }
+}
+
+fn argon2_settings() -> Argon2Settings {
+    Argon2Settings {
+        memory_cost: 4096,
+        time_cost: 1,
+        parallelism: 1,
+    }
+}
+
+async fn local_user(pool: &PgPool) -> User {
+    insert_user(
+        pool,
+        User {
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            password: "not-a-hash".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("User should be inserted.")
+}
+
+#[tokio::test]
+async fn pkce_exchange_returns_verified_claims_once() {
+    let issuer = MockIssuer::spawn().await;
+    let settings = issuer.settings();
+    let mut client = OidcClient::new(&settings, common::test_redis().await);
+
+    let url = client.authorization_url().await.expect("Discovery should work.");
+    let (state, code) = issuer.authorize(&url);
+
+    let claims = client.exchange(&code, &state).await.expect("Exchange should work.");
+    assert_eq!(claims.iss, issuer.url);
+    assert_eq!(claims.email.as_deref(), Some("alice@example.com"));
+
+    // the state is used up
+    assert!(client.exchange(&code, &state).await.is_err());
+}
+
+#[tokio::test]
+async fn exchange_rejects_a_nonce_mismatch() {
+    let issuer = MockIssuer::spawn().await;
+    *issuer.nonce.lock().unwrap() = Some("another nonce".to_string());
+    let settings = issuer.settings();
+    let mut client = OidcClient::new(&settings, common::test_redis().await);
+
+    let url = client.authorization_url().await.expect("Discovery should work.");
+    let (state, code) = issuer.authorize(&url);
+
+    assert!(client.exchange(&code, &state).await.is_err());
+}
+
+#[sqlx::test]
+async fn sign_in_provisions_a_verified_user_and_links_the_identity(pool: PgPool) {
+    let user = sign_in(&pool, &argon2_settings(), claims(true))
+        .await
+        .expect("User should be provisioned.");
+    assert_eq!(user.email, "alice@example.com");
+    assert!(user.verified_at.is_some());
+
+    let again = sign_in(&pool, &argon2_settings(), claims(true))
+        .await
+        .expect("Linked identity should sign in.");
+    assert_eq!(again.id, user.id);
+}
+
+#[sqlx::test]
+async fn sign_in_does_not_link_an_unverified_account(pool: PgPool) {
+    let user = local_user(&pool).await;
+
+    assert!(sign_in(&pool, &argon2_settings(), claims(true)).await.is_err());
+
+    verify_email(&pool, user.id).await.unwrap();
+    let linked = sign_in(&pool, &argon2_settings(), claims(true))
+        .await
+        .expect("Verified account should be linked.");
+    assert_eq!(linked.id, user.id);
+}
+
+#[sqlx::test]
+async fn sign_in_does_not_link_an_unverified_identity_email(pool: PgPool) {
+    let user = local_user(&pool).await;
+    verify_email(&pool, user.id).await.unwrap();
+
+    assert!(sign_in(&pool, &argon2_settings(), claims(false)).await.is_err());
+}
@@ -0,0 +1,192 @@
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use fast_chat::{
+    graphql::{
+        message::schema::{MessageConnection, MessageCursor, PageDirection},
+        room::schema::Room,
+        user::schema::User,
+    },
+    sql::{
+        message::{get_messages, insert_message},
+        room::create_room,
+        user::insert_user,
+    },
+    ws::schema::{MessageAuthor, SocketMessageContent},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+async fn room_with_author(pool: &PgPool) -> (Uuid, MessageAuthor) {
+    let user = insert_user(
+        pool,
+        User {
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            password: "not-a-hash".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("User should be inserted.");
+    let room = create_room(
+        pool,
+        Room {
+            name: "general".to_string(),
+            ..Default::default()
+        },
+        user.id,
+    )
+    .await
+    .expect("Room should be created.");
+
+    (
+        room.id,
+        MessageAuthor {
+            id: user.id,
+            name: user.name,
+        },
+    )
+}
+
+// stored messages at `created_at`, in insertion order
+async fn send_at(
+    pool: &PgPool,
+    room: Uuid,
+    author: &MessageAuthor,
+    created_at: &[DateTime<Utc>],
+) -> Vec<SocketMessageContent> {
+    let mut messages = Vec::new();
+    for created_at in created_at {
+        let message = SocketMessageContent {
+            content: "hello".to_string(),
+            author: author.clone(),
+            room,
+            created_at: *created_at,
+            ..Default::default()
+        };
+        insert_message(pool, message.clone(), &[])
+            .await
+            .expect("Message should be inserted.");
+        messages.push(message);
+    }
+
+    messages
+}
+
+// the page the `messages` query serves for the cursor
+async fn page(
+    pool: &PgPool,
+    room: Uuid,
+    cursor: Option<&str>,
+    direction: PageDirection,
+    limit: usize,
+) -> MessageConnection {
+    let cursor = cursor.map(|cursor| MessageCursor::parse(cursor).expect("Cursor should parse."));
+    let has_cursor = cursor.is_some();
+    let messages = get_messages(pool, room, None, cursor, direction, limit as i64 + 1)
+        .await
+        .expect("Messages should be read.");
+
+    MessageConnection::new(messages, limit, direction, has_cursor)
+}
+
+fn now() -> DateTime<Utc> {
+    // postgres keeps microseconds
+    Utc::now().duration_trunc(Duration::microseconds(1)).unwrap()
+}
+
+// newest first, ties broken by the id
+fn newest_first(mut messages: Vec<SocketMessageContent>) -> Vec<Uuid> {
+    messages.sort_by_key(|message| std::cmp::Reverse((message.created_at, message.id)));
+    messages.into_iter().map(|message| message.id).collect()
+}
+
+// follows `end_cursor` of older pages until there is no next page
+async fn walk_older(pool: &PgPool, room: Uuid, limit: usize) -> Vec<Uuid> {
+    let mut ids = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let connection = page(pool, room, cursor.as_deref(), PageDirection::Older, limit).await;
+        assert!(connection.edges.len() <= limit);
+        assert_eq!(connection.page_info.has_previous_page, cursor.is_some());
+        ids.extend(connection.message_ids());
+        if !connection.page_info.has_next_page {
+            return ids;
+        }
+        cursor = connection.page_info.end_cursor;
+    }
+}
+
+#[sqlx::test]
+async fn older_pages_cover_every_message_once(pool: PgPool) {
+    let (room, author) = room_with_author(&pool).await;
+    let start = now();
+    let times: Vec<_> = (0..5).map(|i| start + Duration::seconds(i)).collect();
+    let messages = send_at(&pool, room, &author, &times).await;
+
+    assert_eq!(walk_older(&pool, room, 2).await, newest_first(messages));
+    // a page ending exactly at the last message has no next page
+    let all = page(&pool, room, None, PageDirection::Older, 5).await;
+    assert_eq!(all.edges.len(), 5);
+    assert!(!all.page_info.has_next_page);
+}
+
+#[sqlx::test]
+async fn newer_pages_lead_back_to_the_newest_message(pool: PgPool) {
+    let (room, author) = room_with_author(&pool).await;
+    let start = now();
+    let times: Vec<_> = (0..5).map(|i| start + Duration::seconds(i)).collect();
+    let messages = send_at(&pool, room, &author, &times).await;
+    let expected = newest_first(messages);
+
+    let oldest = page(&pool, room, None, PageDirection::Older, 5).await;
+    let cursor = oldest.edges.last().unwrap().cursor.clone();
+
+    // newer pages are newest first as well, right before the cursor
+    let newer = page(&pool, room, Some(&cursor), PageDirection::Newer, 2).await;
+    assert_eq!(newer.message_ids(), expected[2..4].to_vec());
+    assert!(newer.page_info.has_previous_page);
+    assert!(newer.page_info.has_next_page);
+
+    let newest = page(
+        &pool,
+        room,
+        newer.page_info.start_cursor.as_deref(),
+        PageDirection::Newer,
+        2,
+    )
+    .await;
+    assert_eq!(newest.message_ids(), expected[..2].to_vec());
+    assert!(!newest.page_info.has_previous_page);
+}
+
+#[sqlx::test]
+async fn messages_with_equal_timestamps_are_paged_by_id(pool: PgPool) {
+    let (room, author) = room_with_author(&pool).await;
+    let times = vec![now(); 5];
+    let messages = send_at(&pool, room, &author, &times).await;
+
+    assert_eq!(walk_older(&pool, room, 2).await, newest_first(messages));
+}
+
+#[test]
+fn cursors_survive_a_round_trip() {
+    let cursor = MessageCursor {
+        created_at: now(),
+        id: Uuid::new_v4(),
+    };
+    let parsed = MessageCursor::parse(&cursor.encode()).unwrap();
+
+    assert_eq!(parsed.created_at, cursor.created_at);
+    assert_eq!(parsed.id, cursor.id);
+}
+
+#[test]
+fn invalid_cursors_are_rejected() {
+    let id = Uuid::new_v4();
+
+    assert!(MessageCursor::parse("").is_err());
+    assert!(MessageCursor::parse("not a cursor").is_err());
+    assert!(MessageCursor::parse(&format!("yesterday|{}", id)).is_err());
+    assert!(MessageCursor::parse("2024-06-01T12:00:00.000000Z|not-a-uuid").is_err());
+    assert!(MessageCursor::parse(&id.to_string()).is_err());
+}
@@ -0,0 +1,105 @@
+use chrono::{Duration, Utc};
+use fast_chat::{
+    graphql::{room::schema::Room, user::schema::User},
+    sql::{
+        message::insert_message,
+        reaction::{delete_reaction, get_reaction_counts, insert_reaction},
+        room::create_room,
+        user::insert_user,
+    },
+    ws::schema::{MessageAuthor, SocketMessageContent, SocketReaction},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+async fn reacted_message(pool: &PgPool) -> (Uuid, MessageAuthor) {
+    let user = insert_user(
+        pool,
+        User {
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            password: "not-a-hash".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("User should be inserted.");
+    let author = MessageAuthor {
+        id: user.id,
+        name: user.name,
+    };
+
+    let room = create_room(
+        pool,
+        Room {
+            name: "general".to_string(),
+            ..Default::default()
+        },
+        author.id,
+    )
+    .await
+    .expect("Room should be created.");
+
+    let message = SocketMessageContent {
+        content: "hello".to_string(),
+        author: author.clone(),
+        room: room.id,
+        ..Default::default()
+    };
+    let id = message.id;
+    insert_message(pool, message, &[])
+        .await
+        .expect("Message should be inserted.");
+
+    (id, author)
+}
+
+fn reaction(id: Uuid, user: &MessageAuthor, minutes_ago: i64) -> SocketReaction {
+    SocketReaction {
+        id,
+        emoji: "👍".to_string(),
+        user: user.clone(),
+        created_at: Utc::now() - Duration::minutes(minutes_ago),
+    }
+}
+
+async fn reaction_count(pool: &PgPool, id: Uuid, user: &MessageAuthor) -> i32 {
+    get_reaction_counts(pool, &[id], user.id)
+        .await
+        .expect("Reactions should be read.")
+        .iter()
+        .map(|count| count.count)
+        .sum()
+}
+
+#[sqlx::test]
+async fn removal_applied_before_an_older_addition_wins(pool: PgPool) {
+    let (id, user) = reacted_message(&pool).await;
+
+    delete_reaction(&pool, reaction(id, &user, 1)).await.unwrap();
+    insert_reaction(&pool, reaction(id, &user, 2)).await.unwrap();
+
+    assert_eq!(reaction_count(&pool, id, &user).await, 0);
+}
+
+#[sqlx::test]
+async fn older_removal_does_not_remove_a_newer_addition(pool: PgPool) {
+    let (id, user) = reacted_message(&pool).await;
+
+    insert_reaction(&pool, reaction(id, &user, 1)).await.unwrap();
+    delete_reaction(&pool, reaction(id, &user, 2)).await.unwrap();
+
+    assert_eq!(reaction_count(&pool, id, &user).await, 1);
+}
+
+#[sqlx::test]
+async fn reaction_can_be_added_again_after_its_removal(pool: PgPool) {
+    let (id, user) = reacted_message(&pool).await;
+
+    insert_reaction(&pool, reaction(id, &user, 3)).await.unwrap();
+    delete_reaction(&pool, reaction(id, &user, 2)).await.unwrap();
+    assert_eq!(reaction_count(&pool, id, &user).await, 0);
+
+    insert_reaction(&pool, reaction(id, &user, 1)).await.unwrap();
+    assert_eq!(reaction_count(&pool, id, &user).await, 1);
+}
@@ -0,0 +1,132 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use fast_chat::{
+    graphql::{room::schema::Room, user::schema::User},
+    service::stream::{AsyncEvent, EventRedisStream, ASYNC_EVENT_MARK_AS_SEEN},
+    sql::{
+        message::{get_message, insert_message},
+        read_cursor::{advance_read_cursor, advances_read_cursor},
+        room::{create_room, join_room},
+        user::insert_user,
+    },
+    ws::schema::{MessageAuthor, MessageStatus, SocketMessageContent, SocketReadReceipt},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+async fn member(pool: &PgPool, name: &str) -> MessageAuthor {
+    let user = insert_user(
+        pool,
+        User {
+            name: name.to_string(),
+            email: format!("{}@example.com", name),
+            password: "not-a-hash".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("User should be inserted.");
+
+    MessageAuthor {
+        id: user.id,
+        name: user.name,
+    }
+}
+
+// a room of alice and bob with three messages of alice, oldest first
+async fn read_room(pool: &PgPool) -> (Uuid, MessageAuthor, Vec<SocketMessageContent>) {
+    let alice = member(pool, "alice").await;
+    let bob = member(pool, "bob").await;
+    let room = create_room(
+        pool,
+        Room {
+            name: "general".to_string(),
+            ..Default::default()
+        },
+        alice.id,
+    )
+    .await
+    .expect("Room should be created.")
+    .id;
+    join_room(pool, room, bob.id).await.unwrap();
+
+    let mut messages = Vec::new();
+    for minutes_ago in [3, 2, 1] {
+        let message = SocketMessageContent {
+            content: "hello".to_string(),
+            author: alice.clone(),
+            room,
+            created_at: Utc::now() - Duration::minutes(minutes_ago),
+            ..Default::default()
+        };
+        insert_message(pool, message.clone(), &[])
+            .await
+            .expect("Message should be inserted.");
+        messages.push(message);
+    }
+
+    (room, bob, messages)
+}
+
+fn receipt(room: Uuid, user: &MessageAuthor, message: &SocketMessageContent) -> SocketReadReceipt {
+    SocketReadReceipt {
+        user: user.clone(),
+        room,
+        message_id: message.id,
+        message_created_at: message.created_at,
+        read_at: Utc::now(),
+    }
+}
+
+#[sqlx::test]
+async fn only_receipts_past_the_stored_cursor_advance_it(pool: PgPool) {
+    let (room, bob, messages) = read_room(&pool).await;
+    let advances = |message: &SocketMessageContent| {
+        let receipt = receipt(room, &bob, message);
+        let pool = pool.clone();
+        async move { advances_read_cursor(&pool, &receipt).await.unwrap() }
+    };
+
+    // without a cursor every receipt advances it
+    assert!(advances(&messages[0]).await);
+
+    advance_read_cursor(&pool, receipt(room, &bob, &messages[1]))
+        .await
+        .unwrap();
+    assert!(!advances(&messages[0]).await);
+    assert!(!advances(&messages[1]).await);
+    assert!(advances(&messages[2]).await);
+}
+
+#[sqlx::test]
+async fn receipts_of_other_users_and_rooms_are_independent(pool: PgPool) {
+    let (room, bob, messages) = read_room(&pool).await;
+    advance_read_cursor(&pool, receipt(room, &bob, &messages[2]))
+        .await
+        .unwrap();
+
+    let carol = member(&pool, "carol").await;
+    assert!(advances_read_cursor(&pool, &receipt(room, &carol, &messages[0]))
+        .await
+        .unwrap());
+    assert!(advances_read_cursor(&pool, &receipt(Uuid::new_v4(), &bob, &messages[0]))
+        .await
+        .unwrap());
+}
+
+#[sqlx::test]
+async fn queued_mark_as_seen_entries_are_still_applied(pool: PgPool) {
+    let (_, _, messages) = read_room(&pool).await;
+    let stream = EventRedisStream::new(ASYNC_EVENT_MARK_AS_SEEN, common::test_redis().await);
+
+    // entries written before read cursors existed
+    let payload = serde_json::json!({ "MarkAsSeen": [messages[0].id, messages[1].id] });
+    let event: AsyncEvent = serde_json::from_value(payload).expect("Entry should decode.");
+    stream.process_event(&pool, event).await.unwrap();
+
+    for (message, seen) in messages.iter().zip([true, true, false]) {
+        let stored = get_message(&pool, message.id).await.unwrap();
+        assert_eq!(matches!(stored.status, MessageStatus::Seen), seen);
+    }
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use fast_chat::{
+    crypt::{
+        refresh::{hash_refresh_token, RefreshToken},
+        token::{authenticate, encode_token, is_token_revoked, Claims},
+    },
+    graphql::user::schema::{SessionUser, User},
+    service::session::{end_sessions, SessionOrigin},
+    sql::{
+        refresh_token::{insert_refresh_token, rotate_refresh_token, Rotation},
+        session::{get_sessions, insert_session, revoke_session},
+        user::insert_user,
+    },
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+async fn alice(pool: &PgPool) -> User {
+    insert_user(
+        pool,
+        User {
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            password: "not-a-hash".to_string(),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("User should be inserted.")
+}
+
+// a login of the user, returns the session id and its refresh token
+async fn log_in(pool: &PgPool, user_id: Uuid) -> (Uuid, String) {
+    let origin = SessionOrigin {
+        ip: "127.0.0.1".parse().unwrap(),
+        user_agent: None,
+        device_label: None,
+    };
+    let sid = insert_session(pool, user_id, &origin)
+        .await
+        .expect("Session should be inserted.");
+    let (token, stored) = RefreshToken::new(user_id, Some(sid), 60);
+    insert_refresh_token(pool, &stored)
+        .await
+        .expect("Refresh token should be inserted.");
+
+    (sid, token)
+}
+
+// a user logged in once, returns the user id, the session id and its refresh token
+async fn logged_in(pool: &PgPool) -> (Uuid, Uuid, String) {
+    let user = alice(pool).await;
+    let (sid, token) = log_in(pool, user.id).await;
+
+    (user.id, sid, token)
+}
+
+fn access_claims(user: &User, sid: Uuid) -> Claims {
+    let session_user = SessionUser {
+        id: user.id,
+        email: &user.email,
+    };
+
+    Claims::new(&session_user, sid, 5)
+}
+
+#[sqlx::test]
+async fn reused_refresh_token_revokes_its_session(pool: PgPool) {
+    let (user_id, sid, token) = logged_in(&pool).await;
+
+    let rotated = rotate_refresh_token(&pool, &hash_refresh_token(&token), 60)
+        .await
+        .unwrap();
+    let Rotation::Rotated(next, _) = rotated else {
+        panic!("A fresh refresh token should rotate.");
+    };
+
+    let reused = rotate_refresh_token(&pool, &hash_refresh_token(&token), 60)
+        .await
+        .unwrap();
+    assert!(matches!(
+        reused,
+        Rotation::Reused { family, user_id: owner } if family == sid && owner == user_id
+    ));
+
+    // the successor belongs to the revoked family as well
+    let successor = rotate_refresh_token(&pool, &hash_refresh_token(&next), 60)
+        .await
+        .unwrap();
+    assert!(matches!(successor, Rotation::Reused { .. }));
+    assert!(get_sessions(&pool, user_id).await.unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn unknown_refresh_token_is_rejected(pool: PgPool) {
+    logged_in(&pool).await;
+
+    let rotation = rotate_refresh_token(&pool, &hash_refresh_token("unknown"), 60)
+        .await
+        .unwrap();
+    assert!(matches!(rotation, Rotation::Rejected));
+}
+
+#[sqlx::test]
+async fn revoked_session_stops_working_and_is_no_longer_listed(pool: PgPool) {
+    let state = common::test_state(pool).await;
+    let user = alice(&state.pool).await;
+    let (current, _) = log_in(&state.pool, user.id).await;
+    let (other, _) = log_in(&state.pool, user.id).await;
+    let current_token = encode_token(&state.keys, &access_claims(&user, current)).unwrap();
+    let other_claims = access_claims(&user, other);
+    let other_token = encode_token(&state.keys, &other_claims).unwrap();
+    assert!(authenticate(&state, &other_token).await.is_ok());
+
+    // what the `revokeSession` mutation does
+    assert!(revoke_session(&state.pool, user.id, other).await.unwrap());
+    end_sessions(
+        &state.pool,
+        &mut state.redis.clone(),
+        &state.chats,
+        user.id,
+        vec![other],
+        5,
+    )
+    .await
+    .unwrap();
+    // revoking twice is not an error, there is nothing left to revoke
+    assert!(!revoke_session(&state.pool, user.id, other).await.unwrap());
+
+    assert!(is_token_revoked(&mut state.redis.clone(), &other_claims)
+        .await
+        .unwrap());
+    assert!(authenticate(&state, &other_token).await.is_err());
+    assert!(authenticate(&state, &current_token).await.is_ok());
+
+    let listed: Vec<Uuid> = get_sessions(&state.pool, user.id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|session| session.id)
+        .collect();
+    assert_eq!(listed, vec![current]);
+}